@@ -0,0 +1,328 @@
+//! Files that are sent with a [Message](crate::Message).
+//!
+//! The content of an attachment can come from a file, from memory or from an async reader:
+//!
+//! ```no_run
+//! use announce_lib::Attachment;
+//!
+//! # async fn example() -> Result<(), announce_lib::Error> {
+//! let mut msg = announce_lib::Message::new("Nightly report");
+//! msg.attachments.push(Attachment::path("./build.log"));
+//! msg.attachments.push(Attachment::bytes(vec![0x89, 0x50, 0x4e, 0x47], "chart.png"));
+//!
+//! let file = tokio::fs::File::open("./report.csv").await?;
+//! let length = file.metadata().await?.len();
+//! msg.attachments
+//!     .push(Attachment::reader(file, Some(length), "report.csv").with_mime("text/csv"));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The MIME type is guessed from the file name, unless it is set explicitly.
+
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncRead;
+
+use crate::message::owned;
+
+/// A file that is sent with a message
+#[derive(Clone, Debug)]
+pub struct Attachment<'a> {
+    /// Where the content of the file comes from
+    pub source: Source<'a>,
+
+    /// The name of the file shown by services; the name of the path by default
+    pub filename: Option<Cow<'a, str>>,
+
+    /// The MIME type of the file; guessed from the file name by default
+    pub mime: Option<Cow<'a, str>>,
+}
+
+/// The content of an [Attachment]
+#[derive(Clone)]
+pub enum Source<'a> {
+    /// A file that is read when the message is sent
+    Path(Cow<'a, str>),
+
+    /// Content in memory
+    Bytes(Bytes),
+
+    /// Content that is streamed from a reader.
+    ///
+    /// A reader can only be read once, so a message with a reader can't be retried or sent to
+    /// more than one target. Later attempts fail with [Error::Generic](crate::Error::Generic).
+    Reader {
+        /// The reader, until it is taken for sending
+        reader: SharedReader,
+        /// The length of the content, if it is known
+        length: Option<u64>,
+    },
+}
+
+/// A reader that is shared between clones of an [Attachment] and can be taken once
+pub type SharedReader = Arc<Mutex<Option<Box<dyn AsyncRead + Send + Sync + Unpin>>>>;
+
+impl<'a> Attachment<'a> {
+    /// An attachment that is read from a file
+    pub fn path(path: impl Into<Cow<'a, str>>) -> Self {
+        Self::new(Source::Path(path.into()))
+    }
+
+    /// An attachment with content from memory
+    pub fn bytes(bytes: impl Into<Bytes>, filename: impl Into<Cow<'a, str>>) -> Self {
+        Self::new(Source::Bytes(bytes.into())).with_filename(filename)
+    }
+
+    /// An attachment that is streamed from a reader; `length` should be given if it is known
+    pub fn reader(
+        reader: impl AsyncRead + Send + Sync + Unpin + 'static,
+        length: Option<u64>,
+        filename: impl Into<Cow<'a, str>>,
+    ) -> Self {
+        let reader: Box<dyn AsyncRead + Send + Sync + Unpin> = Box::new(reader);
+        Self::new(Source::Reader {
+            reader: Arc::new(Mutex::new(Some(reader))),
+            length,
+        })
+        .with_filename(filename)
+    }
+
+    fn new(source: Source<'a>) -> Self {
+        Self {
+            source,
+            filename: None,
+            mime: None,
+        }
+    }
+
+    /// Sets the name of the file shown by services
+    pub fn with_filename(mut self, filename: impl Into<Cow<'a, str>>) -> Self {
+        self.filename = Some(filename.into());
+        self
+    }
+
+    /// Sets the MIME type instead of guessing it from the file name
+    pub fn with_mime(mut self, mime: impl Into<Cow<'a, str>>) -> Self {
+        self.mime = Some(mime.into());
+        self
+    }
+
+    /// Returns the name of the file shown by services
+    pub fn file_name(&self) -> Cow<'_, str> {
+        match (&self.filename, &self.source) {
+            (Some(name), _) => Cow::Borrowed(name),
+            (None, Source::Path(path)) => std::path::Path::new(path.as_ref())
+                .file_name()
+                .map_or(Cow::Borrowed(path.as_ref()), |name| name.to_string_lossy()),
+            (None, _) => Cow::Borrowed("file"),
+        }
+    }
+
+    /// Returns the MIME type of the file, if it is set or can be guessed from the file name
+    pub fn mime_type(&self) -> Option<Cow<'_, str>> {
+        match &self.mime {
+            Some(mime) => Some(Cow::Borrowed(mime)),
+            None => mime_guess::from_path(self.file_name().as_ref())
+                .first()
+                .map(|mime| Cow::Owned(String::from(mime.essence_str()))),
+        }
+    }
+
+    /// Returns the attachment as part of a multipart form.
+    ///
+    /// Files and readers are streamed when the request is sent.
+    pub async fn part(&self) -> Result<reqwest::multipart::Part, crate::Error> {
+        let part = match &self.source {
+            Source::Path(path) => {
+                let file = tokio::fs::File::open(path.as_ref()).await?;
+                let length = file.metadata().await?.len();
+                reqwest::multipart::Part::stream_with_length(stream(file), length)
+            }
+            Source::Bytes(bytes) => reqwest::multipart::Part::stream_with_length(
+                reqwest::Body::from(bytes.clone()),
+                bytes.len() as u64,
+            ),
+            Source::Reader { reader, length } => {
+                let reader = reader
+                    .lock()
+                    .map_err(|_| crate::Error::Generic(String::from("reader is poisoned")))?
+                    .take()
+                    .ok_or_else(|| {
+                        crate::Error::Generic(format!(
+                            "the reader of attachment \"{}\" was already read",
+                            self.file_name()
+                        ))
+                    })?;
+                match length {
+                    Some(length) => {
+                        reqwest::multipart::Part::stream_with_length(stream(reader), *length)
+                    }
+                    None => reqwest::multipart::Part::stream(stream(reader)),
+                }
+            }
+        };
+
+        let part = part.file_name(self.file_name().into_owned());
+        match self.mime_type() {
+            Some(mime) => Ok(part.mime_str(&mime)?),
+            None => Ok(part),
+        }
+    }
+
+    /// Converts the attachment into one that doesn't borrow any data
+    pub fn into_owned(self) -> Attachment<'static> {
+        Attachment {
+            source: match self.source {
+                Source::Path(path) => Source::Path(owned(path)),
+                Source::Bytes(bytes) => Source::Bytes(bytes),
+                Source::Reader { reader, length } => Source::Reader { reader, length },
+            },
+            filename: self.filename.map(owned),
+            mime: self.mime.map(owned),
+        }
+    }
+}
+
+impl Attachment<'static> {
+    /// Fills the placeholders of the path and the file name in place
+    pub(crate) fn fill(&mut self, ctx: &crate::template::Context) -> Result<(), crate::Error> {
+        use crate::template::{render_field, render_opt, Markup};
+
+        if let Source::Path(path) = &mut self.source {
+            render_field(path, ctx, Markup::Plain)?;
+        }
+        render_opt(&mut self.filename, ctx, Markup::Plain)
+    }
+}
+
+/// Turns a reader into a body for requests
+fn stream(reader: impl AsyncRead + Send + Sync + 'static) -> reqwest::Body {
+    let stream = tokio_util::codec::FramedRead::new(reader, tokio_util::codec::BytesCodec::new());
+    reqwest::Body::wrap_stream(stream)
+}
+
+impl std::fmt::Debug for Source<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Path(path) => f.debug_tuple("Path").field(path).finish(),
+            Source::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            Source::Reader { length, .. } => {
+                f.debug_struct("Reader").field("length", length).finish()
+            }
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Attachment<'a> {
+    fn from(path: &'a str) -> Self {
+        Attachment::path(path)
+    }
+}
+
+impl From<String> for Attachment<'_> {
+    fn from(path: String) -> Self {
+        Attachment::path(path)
+    }
+}
+
+impl<'a> From<Cow<'a, str>> for Attachment<'a> {
+    fn from(path: Cow<'a, str>) -> Self {
+        Attachment::path(path)
+    }
+}
+
+/// The forms an attachment can be written in; only files can be (de)serialized
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum AttachmentDef<'a> {
+    Path(Cow<'a, str>),
+    Table {
+        path: Cow<'a, str>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        filename: Option<Cow<'a, str>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mime: Option<Cow<'a, str>>,
+    },
+}
+
+impl Serialize for Attachment<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let path = match &self.source {
+            Source::Path(path) => Cow::Borrowed(path.as_ref()),
+            _ => {
+                return Err(serde::ser::Error::custom(
+                    "only attachments from files can be serialized",
+                ))
+            }
+        };
+        let def = match (&self.filename, &self.mime) {
+            (None, None) => AttachmentDef::Path(path),
+            (filename, mime) => AttachmentDef::Table {
+                path,
+                filename: filename.as_deref().map(Cow::Borrowed),
+                mime: mime.as_deref().map(Cow::Borrowed),
+            },
+        };
+        def.serialize(serializer)
+    }
+}
+
+impl<'de, 'a> Deserialize<'de> for Attachment<'a> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match AttachmentDef::deserialize(deserializer)? {
+            AttachmentDef::Path(path) => Attachment::path(path),
+            AttachmentDef::Table {
+                path,
+                filename,
+                mime,
+            } => Attachment {
+                source: Source::Path(path),
+                filename,
+                mime,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Attachment;
+
+    #[test]
+    fn test_name_and_mime() {
+        let file = Attachment::path("./sample_uploads/rustacean-flat-happy.png");
+        assert_eq!("rustacean-flat-happy.png", file.file_name());
+        assert_eq!(Some("image/png"), file.mime_type().as_deref());
+
+        let bytes = Attachment::bytes(&b"a,b"[..], "data").with_mime("text/csv");
+        assert_eq!("data", bytes.file_name());
+        assert_eq!(Some("text/csv"), bytes.mime_type().as_deref());
+    }
+
+    #[tokio::test]
+    async fn test_reader_is_read_once() {
+        let attachment = Attachment::reader(&b"content"[..], Some(7), "content.txt");
+        let copy = attachment.clone();
+        assert!(attachment.part().await.is_ok());
+        assert!(matches!(copy.part().await, Err(crate::Error::Generic(_))));
+    }
+
+    #[test]
+    fn test_serde() {
+        let json = r#"["./a.log", { "path": "./b.bin", "mime": "image/png" }]"#;
+        let attachments: Vec<Attachment> = serde_json::from_str(json).unwrap();
+        assert_eq!("a.log", attachments[0].file_name());
+        assert_eq!(Some("image/png"), attachments[1].mime_type().as_deref());
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(json).unwrap(),
+            serde_json::to_value(&attachments).unwrap()
+        );
+
+        let bytes = Attachment::bytes(vec![1, 2, 3], "data.bin");
+        assert!(serde_json::to_string(&bytes).is_err());
+    }
+}
@@ -0,0 +1,346 @@
+//! Collecting messages and sending them as digests.
+//!
+//! A [Batcher] collects the messages for every target and sends them as one combined message
+//! per target after an interval, or as soon as a target has enough messages. This is useful for
+//! low priority events, which would otherwise be a stream of messages:
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//! use announce_lib::batch::Batcher;
+//!
+//! # async fn example() -> Result<(), announce_lib::Error> {
+//! let announce = Arc::new(announce_lib::Announce::new().await?);
+//! let batcher = Batcher::spawn(announce, Duration::from_secs(10 * 60));
+//!
+//! let urls = vec![reqwest::Url::parse("discord://WEBHOOK_ID/WEBHOOK_TOKEN")?];
+//! batcher.push(urls, &announce_lib::Message::new("job 42 finished"))?;
+//!
+//! // sends what was collected before the process exits
+//! for (target, result) in batcher.shutdown().await {
+//!     println!("{}: {:?}", target, result);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Discord shows the messages of a batch as fields of an embed and splits batches that exceed
+//! its limits, Rocket.Chat shows them as a list of attachments and D-Bus as lines of the body,
+//! see [Message::combine]. A target with a single message gets it as it is. Errors of sending
+//! after the interval are logged; use [Batcher::flush] to get them.
+
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use futures::FutureExt;
+
+use crate::{Announce, DeliveryReceipt, Error, Message};
+
+/// The number of messages of a target that are sent right away; the number of fields of an
+/// embed of Discord
+pub const DEFAULT_MAX_ITEMS: usize = 25;
+
+/// Collects messages per target and sends them combined, see [the module](self)
+pub struct Batcher {
+    shared: Arc<Shared>,
+    stop: tokio::sync::oneshot::Sender<()>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+struct Shared {
+    announce: Arc<Announce>,
+    max_items: usize,
+    /// The collected messages by target in the order the targets were added
+    queues: Mutex<Vec<(reqwest::Url, Vec<Message<'static>>)>>,
+    /// The sends of full targets that run in the background
+    sends: Mutex<tokio::task::JoinSet<()>>,
+}
+
+/// The result of sending a batch to a target, which is shown without secrets
+pub type BatchResult = (String, Result<DeliveryReceipt, Error>);
+
+impl Batcher {
+    /// Starts a batcher that sends the collected messages every `interval`.
+    ///
+    /// Targets with [DEFAULT_MAX_ITEMS] messages are sent right away.
+    pub fn spawn(announce: Arc<Announce>, interval: Duration) -> Self {
+        Self::spawn_with_max_items(announce, interval, DEFAULT_MAX_ITEMS)
+    }
+
+    /// Starts a batcher that sends the collected messages every `interval` and the messages of
+    /// a target as soon as it has `max_items` of them.
+    ///
+    /// `max_items` is at most [DEFAULT_MAX_ITEMS], more messages don't fit into one embed.
+    pub fn spawn_with_max_items(
+        announce: Arc<Announce>,
+        interval: Duration,
+        max_items: usize,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            announce,
+            max_items: max_items.clamp(1, DEFAULT_MAX_ITEMS),
+            queues: Mutex::new(vec![]),
+            sends: Mutex::new(tokio::task::JoinSet::new()),
+        });
+
+        let (stop, mut stopped) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn({
+            let shared = shared.clone();
+            async move {
+                let mut timer = tokio::time::interval(interval);
+                // the first tick of an interval is right away
+                timer.tick().await;
+                loop {
+                    tokio::select! {
+                        _ = &mut stopped => break,
+                        _ = timer.tick() => log_errors(shared.flush().await),
+                    }
+                }
+            }
+        });
+
+        Self { shared, stop, task }
+    }
+
+    /// Adds a message for the given targets.
+    ///
+    /// Targets that reach the maximum number of messages are sent in the background. Returns
+    /// an error if a url has a scheme without service; the message is not added then.
+    pub fn push(&self, urls: Vec<reqwest::Url>, msg: &Message<'_>) -> Result<(), Error> {
+        let services = self.shared.announce.services();
+        if urls.iter().any(|url| services.get(url.scheme()).is_none()) {
+            return Err(Error::NoMatchingSchema);
+        }
+
+        let mut full = vec![];
+        {
+            let mut queues = self.shared.lock();
+            for url in urls {
+                let index = match queues.iter().position(|(target, _)| *target == url) {
+                    Some(index) => index,
+                    None => {
+                        queues.push((url, vec![]));
+                        queues.len() - 1
+                    }
+                };
+                queues[index].1.push(msg.clone().into_owned());
+                if queues[index].1.len() >= self.shared.max_items {
+                    full.push(queues.remove(index));
+                }
+            }
+        }
+
+        if !full.is_empty() {
+            let shared = self.shared.clone();
+            let mut sends = self
+                .shared
+                .sends
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            // forgets the sends that are done already
+            while let Some(Some(_)) = sends.join_next().now_or_never() {}
+            sends.spawn(async move { log_errors(shared.send(full).await) });
+        }
+        Ok(())
+    }
+
+    /// Returns the number of messages that are collected but not sent yet
+    pub fn pending(&self) -> usize {
+        self.shared
+            .lock()
+            .iter()
+            .map(|(_, messages)| messages.len())
+            .sum()
+    }
+
+    /// Sends the collected messages of every target now
+    pub async fn flush(&self) -> Vec<BatchResult> {
+        self.shared.flush().await
+    }
+
+    /// Stops the timer, waits for the sends in the background and sends the messages that are
+    /// left
+    pub async fn shutdown(self) -> Vec<BatchResult> {
+        let _ = self.stop.send(());
+        let _ = self.task.await;
+        let mut sends = std::mem::take(
+            &mut *self
+                .shared
+                .sends
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        while sends.join_next().await.is_some() {}
+        self.shared.flush().await
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<(reqwest::Url, Vec<Message<'static>>)>> {
+        self.queues.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    async fn flush(&self) -> Vec<BatchResult> {
+        let queues = std::mem::take(&mut *self.lock());
+        self.send(queues).await
+    }
+
+    async fn send(&self, queues: Vec<(reqwest::Url, Vec<Message<'static>>)>) -> Vec<BatchResult> {
+        let announce = self.announce.as_ref();
+        let mut results = vec![];
+        for (url, messages) in &queues {
+            let result = crate::service::decide_batch(announce, url, messages).await;
+            results.push((announce.redact(url), result));
+        }
+        results
+    }
+}
+
+fn log_errors(results: Vec<BatchResult>) {
+    for (target, result) in results {
+        if let Err(e) = result {
+            log::warn!("sending a batch to {} failed: {}", target, e);
+        }
+    }
+}
+
+// the tests send to Discord through a mock transport
+#[cfg(all(test, feature = "discord"))]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::Batcher;
+    use crate::transport::{MockResponse, MockTransport};
+    use crate::{Announce, Message};
+
+    fn announce(transport: Arc<MockTransport>) -> Arc<Announce> {
+        Arc::new(Announce::builder().transport(transport).build().unwrap())
+    }
+
+    fn urls(urls: &[&str]) -> Vec<reqwest::Url> {
+        urls.iter()
+            .map(|url| reqwest::Url::parse(url).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_messages_are_combined_per_target() {
+        let transport = Arc::new(MockTransport::new());
+        let batcher = Batcher::spawn(announce(transport.clone()), Duration::from_secs(3600));
+
+        let both = urls(&["discord://1/token", "discord://2/token"]);
+        batcher.push(both.clone(), &Message::new("first")).unwrap();
+        batcher.push(both, &Message::new("second")).unwrap();
+        batcher
+            .push(urls(&["discord://3/token"]), &Message::new("single"))
+            .unwrap();
+        assert_eq!(5, batcher.pending());
+        assert!(transport.requests().is_empty());
+
+        let results = batcher.shutdown().await;
+        assert_eq!(3, results.len());
+        assert_eq!("discord://1/***", results[0].0);
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+
+        let requests = transport.requests();
+        let json = requests[0].json().unwrap();
+        assert_eq!("2 messages", json["content"]);
+        assert_eq!("first", json["embeds"][0]["fields"][0]["value"]);
+        assert_eq!("second", json["embeds"][0]["fields"][1]["value"]);
+        // a single message is sent as it is
+        assert_eq!("single", requests[2].json().unwrap()["content"]);
+
+        assert!(matches!(
+            Batcher::spawn(announce(transport), Duration::from_secs(1))
+                .push(urls(&["unknown://x"]), &Message::new("text")),
+            Err(crate::Error::NoMatchingSchema)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_timer_and_max_items() {
+        let transport = Arc::new(MockTransport::new());
+        let batcher = Batcher::spawn_with_max_items(
+            announce(transport.clone()),
+            Duration::from_millis(50),
+            2,
+        );
+
+        let target = urls(&["discord://1/token"]);
+        batcher.push(target.clone(), &Message::new("one")).unwrap();
+        batcher.push(target.clone(), &Message::new("two")).unwrap();
+        batcher.push(target, &Message::new("three")).unwrap();
+        // the full batch is sent right away, the rest after the interval
+        for _ in 0..100 {
+            if transport.requests().len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let requests = transport.requests();
+        assert_eq!(2, requests.len());
+        assert_eq!("2 messages", requests[0].json().unwrap()["content"]);
+        assert_eq!("three", requests[1].json().unwrap()["content"]);
+        assert_eq!(0, batcher.pending());
+        assert!(batcher.shutdown().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_full_targets() {
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(MockResponse::rate_limited(Duration::from_millis(100)));
+        let batcher = Batcher::spawn_with_max_items(
+            announce(transport.clone()),
+            Duration::from_secs(3600),
+            1,
+        );
+
+        batcher
+            .push(urls(&["discord://1/token"]), &Message::new("one"))
+            .unwrap();
+        assert_eq!(0, batcher.pending());
+        assert!(batcher.shutdown().await.is_empty());
+        // the retry after the rate limit is sent before shutdown returns
+        assert_eq!(2, transport.requests().len());
+    }
+
+    #[tokio::test]
+    async fn test_long_messages_are_split() {
+        let transport = Arc::new(MockTransport::new());
+        // more messages than fields of an embed are not collected
+        let batcher = Batcher::spawn_with_max_items(
+            announce(transport.clone()),
+            Duration::from_secs(3600),
+            100,
+        );
+
+        let target = urls(&["discord://1/token"]);
+        let text = "x".repeat(1000);
+        for _ in 0..25 {
+            batcher
+                .push(target.clone(), &Message::new(text.as_str()))
+                .unwrap();
+        }
+        assert_eq!(0, batcher.pending());
+        for _ in 0..100 {
+            if transport.requests().len() == 5 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // 25 fields of 1000 characters exceed the 6000 characters of the embeds of a message
+        let requests = transport.requests();
+        assert_eq!(5, requests.len());
+        let mut fields = 0;
+        for request in &requests {
+            let msg: crate::service::discord::Message =
+                serde_json::from_value(request.json().unwrap()).unwrap();
+            msg.validate().unwrap();
+            fields += msg.embeds[0].fields.len();
+        }
+        assert_eq!(25, fields);
+        assert!(batcher.shutdown().await.is_empty());
+    }
+}
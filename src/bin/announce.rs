@@ -0,0 +1,423 @@
+//! Command line interface to send messages to the services supported by announce.
+//!
+//! Run `announce --help` for all options.
+
+use std::borrow::Cow;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use announce_lib::config::{Config, TargetDefaults};
+use announce_lib::{Announce, Attachment, DeliveryReceipt, Error, Hint, Message, RetryPolicy};
+use clap::Parser;
+
+/// Sends a message to chat services and desktop notifications
+#[derive(Parser)]
+#[command(name = "announce", version)]
+struct Args {
+    /// Urls of targets (e.g. discord://WEBHOOK_ID/WEBHOOK_TOKEN) or names of targets and groups
+    /// of the config file
+    #[arg(required = true)]
+    targets: Vec<String>,
+
+    /// Text of the message; read from stdin if missing
+    #[arg(short, long)]
+    text: Option<String>,
+
+    /// Config file with named targets and groups
+    #[arg(short, long, env = "ANNOUNCE_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Adds a link to the message
+    #[arg(long)]
+    link: Option<String>,
+
+    /// Adds a description to the message
+    #[arg(long)]
+    description: Option<String>,
+
+    /// Attaches a file to the message; can be given multiple times
+    #[arg(long)]
+    file: Vec<String>,
+
+    /// How many targets are served at the same time
+    #[arg(long)]
+    concurrency: Option<usize>,
+
+    /// How often a request is sent at most
+    #[arg(long)]
+    attempts: Option<u32>,
+
+    /// Splits texts that are too long for a service into several messages
+    #[arg(long)]
+    split: bool,
+
+    #[cfg(feature = "discord")]
+    #[command(flatten)]
+    discord: DiscordArgs,
+
+    #[cfg(feature = "rocketchat")]
+    #[command(flatten)]
+    rocketchat: RocketChatArgs,
+
+    #[cfg(feature = "dbus")]
+    #[command(flatten)]
+    dbus: DbusArgs,
+}
+
+#[cfg(feature = "discord")]
+#[derive(clap::Args)]
+#[command(next_help_heading = "Discord")]
+struct DiscordArgs {
+    /// Overrides the name of the webhook
+    #[arg(long = "discord-username")]
+    username: Option<String>,
+
+    /// Overrides the avatar of the webhook
+    #[arg(long = "discord-avatar-url")]
+    avatar_url: Option<String>,
+
+    /// Sends the message as text to speech message
+    #[arg(long = "discord-tts")]
+    tts: bool,
+}
+
+#[cfg(feature = "rocketchat")]
+#[derive(clap::Args)]
+#[command(next_help_heading = "Rocket.Chat")]
+struct RocketChatArgs {
+    /// Shows the message under this name
+    #[arg(long = "rocketchat-alias")]
+    alias: Option<String>,
+
+    /// Uses an emoji (e.g. :smirk:) as avatar
+    #[arg(long = "rocketchat-emoji")]
+    emoji: Option<String>,
+
+    /// Uses the image of this url as avatar
+    #[arg(long = "rocketchat-avatar")]
+    avatar: Option<String>,
+}
+
+#[cfg(feature = "dbus")]
+#[derive(clap::Args)]
+#[command(next_help_heading = "D-Bus")]
+struct DbusArgs {
+    /// The single line overview of the notification
+    #[arg(long = "dbus-summary")]
+    summary: Option<String>,
+
+    /// The urgency of the notification (0 = low, 1 = normal, 2 = critical)
+    #[arg(long = "dbus-urgency", value_parser = clap::value_parser!(u8).range(0..=2))]
+    urgency: Option<u8>,
+}
+
+/// A single target after resolving names of the config
+struct Entry<'c> {
+    label: String,
+    url: reqwest::Url,
+    defaults: Option<&'c TargetDefaults>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(args).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("announce: {}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<ExitCode, Error> {
+    let config = match &args.config {
+        Some(path) => Config::from_file(path)?,
+        None => Config::default(),
+    };
+
+    let mut builder = Announce::builder().split_long_messages(args.split);
+    if let Some(limit) = args.concurrency {
+        builder = builder.concurrency(limit);
+    }
+    if let Some(attempts) = args.attempts {
+        builder = builder.retry_policy(RetryPolicy {
+            max_attempts: attempts,
+            ..Default::default()
+        });
+    }
+    let announce = builder.build()?;
+
+    let entries = resolve(&announce, &config, &args.targets)?;
+    let msg = build_message(&args)?;
+
+    let results = send(&announce, &args, &entries, &msg).await;
+
+    let mut failed = false;
+    for (entry, result) in entries.iter().zip(results) {
+        match result {
+            Ok(receipt) if receipt.success => match receipt.message_id {
+                Some(id) => println!("ok      {} (id {})", entry.label, id),
+                None => println!("ok      {}", entry.label),
+            },
+            Ok(receipt) => {
+                failed = true;
+                println!("failed  {}: status {:?}", entry.label, receipt.status);
+            }
+            Err(e) => {
+                failed = true;
+                println!("failed  {}: {}", entry.label, e);
+            }
+        }
+    }
+
+    Ok(if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Turns the target arguments into urls
+fn resolve<'c>(
+    announce: &Announce,
+    config: &'c Config,
+    targets: &[String],
+) -> Result<Vec<Entry<'c>>, Error> {
+    let mut entries = vec![];
+    for target in targets {
+        if target.contains("://") {
+            let url = reqwest::Url::parse(target)?;
+            entries.push(Entry {
+                label: announce.redact(&url),
+                url,
+                defaults: None,
+            });
+        } else {
+            for (name, target) in config.resolve(target)? {
+                entries.push(Entry {
+                    label: String::from(name),
+                    url: target.url.clone(),
+                    defaults: Some(&target.defaults),
+                });
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Builds the message from the arguments and stdin
+fn build_message(args: &Args) -> Result<Message<'_>, Error> {
+    let text = match &args.text {
+        Some(text) => Cow::Borrowed(text.as_str()),
+        None => {
+            let mut text = String::new();
+            std::io::stdin().read_to_string(&mut text)?;
+            Cow::Owned(String::from(text.trim_end()))
+        }
+    };
+    if text.is_empty() && args.file.is_empty() {
+        return Err(Error::Generic(String::from("no text or file to send")));
+    }
+
+    let mut msg = Message::new(text);
+    if let Some(link) = &args.link {
+        msg.hints.push(Hint::Link(link.into()));
+    }
+    if let Some(description) = &args.description {
+        msg.hints.push(Hint::Description(description.into()));
+    }
+    msg.attachments = args
+        .file
+        .iter()
+        .map(|path| Attachment::path(path.as_str()))
+        .collect();
+
+    Ok(msg)
+}
+
+/// Sends the message to all entries and returns the results in the same order
+async fn send(
+    announce: &Announce,
+    args: &Args,
+    entries: &[Entry<'_>],
+    msg: &Message<'_>,
+) -> Vec<Result<DeliveryReceipt, Error>> {
+    let messages: Vec<_> = entries
+        .iter()
+        .map(|entry| match entry.defaults {
+            Some(defaults) => defaults.apply(msg),
+            None => Cow::Borrowed(msg),
+        })
+        .collect();
+
+    let mut results: Vec<Option<Result<DeliveryReceipt, Error>>> =
+        entries.iter().map(|_| None).collect();
+
+    // targets with service specific options use the modules of the services
+    let specific: Vec<_> = entries
+        .iter()
+        .zip(&messages)
+        .enumerate()
+        .filter(|(_, (entry, _))| has_specific_args(args, &entry.url))
+        .map(|(i, (entry, msg))| async move {
+            (i, send_specific(announce, args, &entry.url, msg).await)
+        })
+        .collect();
+
+    // all other targets with the same message are sent together
+    let (plain, with_defaults): (Vec<_>, Vec<_>) = (0..entries.len())
+        .filter(|&i| !has_specific_args(args, &entries[i].url))
+        .partition(|&i| matches!(messages[i], Cow::Borrowed(_)));
+    let plain_urls = plain.iter().map(|&i| entries[i].url.clone()).collect();
+    let with_defaults = with_defaults.into_iter().map(|i| {
+        let url = entries[i].url.clone();
+        let msg = &messages[i];
+        async move { (i, announce.announce(vec![url], msg).await) }
+    });
+
+    let (specific, plain_results, with_defaults) = tokio::join!(
+        futures::future::join_all(specific),
+        announce.announce(plain_urls, msg),
+        futures::future::join_all(with_defaults),
+    );
+
+    for (i, result) in specific {
+        results[i] = Some(result);
+    }
+    for (i, result) in plain.into_iter().zip(plain_results) {
+        results[i] = Some(result);
+    }
+    for (i, result) in with_defaults {
+        results[i] = result.into_iter().next();
+    }
+
+    results
+        .into_iter()
+        .map(|result| {
+            result.unwrap_or_else(|| Err(Error::Generic(String::from("target was not sent"))))
+        })
+        .collect()
+}
+
+/// Returns true if service specific options are given for the service of the url
+#[allow(unused_variables)]
+fn has_specific_args(args: &Args, url: &reqwest::Url) -> bool {
+    match url.scheme() {
+        #[cfg(feature = "discord")]
+        "discord" => {
+            args.discord.username.is_some() || args.discord.avatar_url.is_some() || args.discord.tts
+        }
+        #[cfg(feature = "rocketchat")]
+        // uploads of files can't be customized, so they use the generic path
+        "rocketchat" | "rocketchats" if args.file.is_empty() => {
+            args.rocketchat.alias.is_some()
+                || args.rocketchat.emoji.is_some()
+                || args.rocketchat.avatar.is_some()
+        }
+        #[cfg(feature = "dbus")]
+        "dbus" => args.dbus.summary.is_some() || args.dbus.urgency.is_some(),
+        _ => false,
+    }
+}
+
+/// Sends a message with the module of a service, so service specific options can be applied
+#[allow(unused_variables)]
+async fn send_specific(
+    announce: &Announce,
+    args: &Args,
+    url: &reqwest::Url,
+    msg: &Message<'_>,
+) -> Result<DeliveryReceipt, Error> {
+    #[allow(unused_imports)]
+    use announce_lib::service::Service;
+
+    let sent_at = chrono::Utc::now();
+    match url.scheme() {
+        #[cfg(feature = "discord")]
+        "discord" => {
+            use announce_lib::service::discord;
+
+            let mut message = discord::Message::from_crate_message(msg);
+            message.username = args.discord.username.as_deref().map(Cow::Borrowed);
+            message.avatar_url = args.discord.avatar_url.as_deref().map(Cow::Borrowed);
+            message.tts = args.discord.tts;
+            let messages = match announce.split_long_messages() {
+                true => message.split(),
+                false => vec![message],
+            };
+            for message in &messages {
+                message.validate()?;
+            }
+
+            let mut receipts = vec![];
+            for message in &messages {
+                let response = discord::Discord::announce_with(announce, url, message).await?;
+                receipts.push(discord::Discord::receipt(url, sent_at, response).await?);
+            }
+            first_receipt(receipts)
+        }
+        #[cfg(feature = "rocketchat")]
+        "rocketchat" | "rocketchats" => {
+            use announce_lib::service::rocketchat;
+
+            let channel = url
+                .path_segments()
+                .and_then(|mut path| path.next())
+                .filter(|channel| !channel.is_empty())
+                .ok_or_else(|| Error::MissingField(String::from("channel")))?;
+            let mut message = rocketchat::Message::from_crate_message(msg, channel);
+            message.alias = args.rocketchat.alias.as_deref().map(Cow::Borrowed);
+            message.emoji = args.rocketchat.emoji.as_deref().map(Cow::Borrowed);
+            message.avatar = args.rocketchat.avatar.as_deref().map(Cow::Borrowed);
+            let max_size = rocketchat::RocketChat::max_size(url)?;
+            let messages = match announce.split_long_messages() {
+                true => message.split(max_size),
+                false => vec![message],
+            };
+            for message in &messages {
+                message.validate(max_size)?;
+            }
+
+            let mut receipts = vec![];
+            for message in &messages {
+                let response =
+                    rocketchat::RocketChat::announce_with(announce, url, message).await?;
+                receipts.push(rocketchat::RocketChat::receipt(url, sent_at, response).await?);
+            }
+            first_receipt(receipts)
+        }
+        #[cfg(feature = "dbus")]
+        "dbus" => {
+            use announce_lib::service::dbus;
+
+            let mut message = dbus::Message::from_crate_message(msg)?;
+            if let Some(summary) = &args.dbus.summary {
+                message.summary = Cow::Borrowed(summary);
+            }
+            let urgency = args.dbus.urgency.map(dbus::message::StandardHint::Urgency);
+            if let Some(urgency) = &urgency {
+                message.add_standard_hint(urgency);
+            }
+            let id = dbus::Dbus::announce(&message).await?;
+            Ok(DeliveryReceipt::new(
+                dbus::Dbus::name(),
+                dbus::Dbus::redact(url),
+                sent_at,
+                Some(id.to_string()),
+            ))
+        }
+        _ => Err(Error::NoMatchingSchema),
+    }
+}
+
+/// Returns the receipt of the first part of a split message
+#[cfg(any(feature = "discord", feature = "rocketchat"))]
+fn first_receipt(receipts: Vec<DeliveryReceipt>) -> Result<DeliveryReceipt, Error> {
+    receipts
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::Generic(String::from("nothing to send")))
+}
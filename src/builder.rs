@@ -0,0 +1,238 @@
+//! Configuration of an [Announce] object.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::service::{DynService, ServiceRegistry};
+use crate::transport::Transport;
+use crate::{Announce, Error, RetryPolicy};
+
+/// Builds an [Announce] object with a custom configuration.
+///
+/// # Example
+/// ```no_run
+/// # fn main() -> Result<(), announce_lib::Error> {
+/// let announce = announce_lib::Announce::builder()
+///     .user_agent("my-app/1.0")
+///     .timeout(std::time::Duration::from_secs(10))
+///     .concurrency(4)
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AnnounceBuilder {
+    client: Option<reqwest::Client>,
+    transport: Option<Arc<dyn Transport>>,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    proxies: Vec<reqwest::Proxy>,
+    user_agent: Option<String>,
+    concurrency: usize,
+    retry: RetryPolicy,
+    split_long_messages: bool,
+    throttle: Option<Duration>,
+    routes: Vec<crate::routing::Rule>,
+    services: ServiceRegistry,
+
+    #[cfg(feature = "config")]
+    config: crate::config::Config,
+
+    #[cfg(feature = "dbus")]
+    dbus_con: Option<zbus::Connection>,
+
+    #[cfg(feature = "outbox")]
+    outbox: Option<crate::outbox::Outbox>,
+}
+
+impl AnnounceBuilder {
+    /// Creates a builder with the default configuration
+    pub fn new() -> Self {
+        Self {
+            client: None,
+            transport: None,
+            connect_timeout: None,
+            timeout: None,
+            proxies: vec![],
+            user_agent: None,
+            concurrency: crate::DEFAULT_CONCURRENCY,
+            retry: RetryPolicy::default(),
+            split_long_messages: false,
+            throttle: None,
+            routes: vec![],
+            services: ServiceRegistry::new(),
+            #[cfg(feature = "config")]
+            config: crate::config::Config::default(),
+            #[cfg(feature = "dbus")]
+            dbus_con: None,
+            #[cfg(feature = "outbox")]
+            outbox: None,
+        }
+    }
+
+    /// Uses the given client for internet Api's.
+    ///
+    /// The client is used as it is, so the timeouts, proxies and the user agent of this
+    /// builder are ignored.
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Executes the requests to internet Api's with the given transport instead of the client.
+    ///
+    /// Requests are still built with the client. See [crate::transport] for details.
+    pub fn transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Sets the timeout for connecting to a service
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the timeout for a whole request, from connecting until the response is received
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Adds a proxy for requests to internet Api's
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxies.push(proxy);
+        self
+    }
+
+    /// Sets the user agent; the default is `announce/VERSION`
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Sets how many targets are served at the same time (see [Announce::set_concurrency])
+    pub fn concurrency(mut self, limit: usize) -> Self {
+        self.concurrency = limit.max(1);
+        self
+    }
+
+    /// Sets how failed requests to internet Api's are retried
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Splits texts that exceed the limits of a service into several messages (see
+    /// [Announce::set_split_long_messages])
+    pub fn split_long_messages(mut self, split: bool) -> Self {
+        self.split_long_messages = split;
+        self
+    }
+
+    /// Suppresses repeats of a message to a target within `window` (see
+    /// [Announce::set_throttle])
+    pub fn throttle(mut self, window: Duration) -> Self {
+        self.throttle = Some(window);
+        self
+    }
+
+    /// Adds a rule that decides targets of [Announce::announce_routed], see [crate::routing]
+    pub fn route(mut self, rule: crate::routing::Rule) -> Self {
+        self.routes.push(rule);
+        self
+    }
+
+    /// Registers a service for all of its schemes (see [Announce::register_service])
+    pub fn register_service<S: DynService + 'static>(mut self, service: S) -> Self {
+        self.services.register(service);
+        self
+    }
+
+    /// Replaces all services with the given ones
+    pub fn services(mut self, services: ServiceRegistry) -> Self {
+        self.services = services;
+        self
+    }
+
+    /// Sets the named targets and groups used by [Announce::announce_to]
+    #[cfg(feature = "config")]
+    pub fn config(mut self, config: crate::config::Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Uses the given connection for dbus instead of opening a session connection
+    #[cfg(feature = "dbus")]
+    pub fn dbus_connection(mut self, connection: zbus::Connection) -> Self {
+        self.dbus_con = Some(connection);
+        self
+    }
+
+    /// Sets the outbox used by [Announce::enqueue], see [crate::outbox]
+    #[cfg(feature = "outbox")]
+    pub fn outbox(mut self, outbox: crate::outbox::Outbox) -> Self {
+        self.outbox = Some(outbox);
+        self
+    }
+
+    /// Creates the [Announce] object.
+    ///
+    /// No connection to dbus is opened here, this happens when a message is sent to a dbus
+    /// target for the first time.
+    pub fn build(self) -> Result<Announce, Error> {
+        let client = match self.client {
+            Some(client) => client,
+            None => {
+                let user_agent = self
+                    .user_agent
+                    .unwrap_or_else(|| format!("announce/{}", env!("CARGO_PKG_VERSION")));
+                let mut builder = reqwest::ClientBuilder::new()
+                    .use_rustls_tls()
+                    .user_agent(user_agent);
+                if let Some(timeout) = self.connect_timeout {
+                    builder = builder.connect_timeout(timeout);
+                }
+                if let Some(timeout) = self.timeout {
+                    builder = builder.timeout(timeout);
+                }
+                for proxy in self.proxies {
+                    builder = builder.proxy(proxy);
+                }
+                builder.build()?
+            }
+        };
+
+        #[cfg(feature = "dbus")]
+        let dbus_con = match self.dbus_con {
+            Some(connection) => tokio::sync::OnceCell::new_with(Some(connection)),
+            None => tokio::sync::OnceCell::new(),
+        };
+
+        let transport = self
+            .transport
+            .unwrap_or_else(|| Arc::new(client.clone()) as Arc<dyn Transport>);
+
+        Ok(Announce {
+            client,
+            transport,
+            concurrency: self.concurrency,
+            retry: self.retry,
+            split_long_messages: self.split_long_messages,
+            services: self.services,
+            throttle: self.throttle.map(crate::throttle::Throttle::new),
+            routes: self.routes,
+            #[cfg(feature = "config")]
+            config: self.config,
+            #[cfg(feature = "dbus")]
+            dbus_con,
+            #[cfg(feature = "outbox")]
+            outbox: self.outbox,
+        })
+    }
+}
+
+impl Default for AnnounceBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
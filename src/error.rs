@@ -5,15 +5,39 @@ use thiserror::Error;
 /// Contains every Error which can be encountered in announce.
 #[derive(Error, Debug)]
 pub enum Error {
-    /// Error while handling requests
+    /// Error while handling requests; secrets of its url are replaced with `***`
     #[error("reqwest error: {0}")]
-    Reqwest(#[from] reqwest::Error),
+    Reqwest(reqwest::Error),
 
     #[cfg(feature = "dbus")]
     /// Error while handling zbus library
     #[error("Zbus error: {0}")]
     Zbus(#[from] zbus::Error),
 
+    #[cfg(feature = "config")]
+    /// Error while parsing a TOML configuration
+    ///
+    /// The message doesn't contain the content of the configuration, which could contain
+    /// secrets, use the span of the error to show it.
+    #[error("TOML error: {}", .0.message())]
+    Toml(#[from] toml::de::Error),
+
+    /// A configuration that is syntactically correct but not valid
+    #[error("invalid configuration: {0}")]
+    Config(String),
+
+    /// A name that is neither a target nor a group of the configuration
+    #[error("unknown target or group: {0}")]
+    UnknownTarget(String),
+
+    /// A template that can not be rendered
+    #[error("template error: {0}")]
+    Template(String),
+
+    /// A message that matches no [routing rule](crate::routing::Rule)
+    #[error("no routing rule matches the message")]
+    NoRoute,
+
     /// Error when no matching schema was found
     #[error("Schema does not match a supported service (create an issue for a new service)")]
     NoMatchingSchema,
@@ -38,7 +62,174 @@ pub enum Error {
     #[error("Error handling io")]
     Fs(#[from] std::io::Error),
 
+    /// A connection to a service failed or timed out; such requests are retried
+    #[error("connection error: {0}")]
+    Connection(String),
+
+    /// A request kept failing until the [RetryPolicy](crate::retry::RetryPolicy) ran out of attempts
+    #[error("giving up after {attempts} attempts (last status: {status:?})")]
+    RetriesExhausted {
+        /// How often the request was sent
+        attempts: u32,
+        /// The status of the last response, if the service responded at all
+        status: Option<reqwest::StatusCode>,
+        /// The time the service asked to wait before sending again
+        retry_after: Option<std::time::Duration>,
+        /// The error of the last attempt, if no response was received
+        #[source]
+        source: Option<Box<Error>>,
+    },
+
+    /// A service rejected a request for a reason without a variant of its own
+    #[error("{service} api error: {message} (status: {status:?}, code: {code:?})")]
+    Api {
+        /// The name of the service
+        service: &'static str,
+        /// The HTTP status of the response
+        status: Option<u16>,
+        /// The code of the error as the service names it, e.g. `50035` for Discord or
+        /// `error-invalid-channel` for Rocket.Chat
+        code: Option<String>,
+        /// The message of the service
+        message: String,
+    },
+
+    /// A service still limited the rate of requests after all retries
+    #[error("rate limited (retry after: {retry_after:?})")]
+    RateLimited {
+        /// The time the service asked to wait before sending again
+        retry_after: Option<std::time::Duration>,
+    },
+
+    /// A service rejected the credentials of the url
+    #[error("{service} rejected the credentials: {message}")]
+    Unauthorized {
+        /// The name of the service
+        service: &'static str,
+        /// The message of the service
+        message: String,
+    },
+
+    /// A target that doesn't exist (anymore), e.g. a deleted webhook or an unknown room
+    #[error("{service} doesn't know the target: {message}")]
+    NotFound {
+        /// The name of the service
+        service: &'static str,
+        /// The message of the service
+        message: String,
+    },
+
+    /// A message that the service rejected because of its size, e.g. a large attachment
+    #[error("the message is too large for {service}: {message}")]
+    PayloadTooLarge {
+        /// The name of the service
+        service: &'static str,
+        /// The message of the service
+        message: String,
+    },
+
+    /// A repeat of a message that was not sent because of the [throttle](crate::throttle)
+    #[error("suppressed repeat {repeats} of a message within the throttling window")]
+    Suppressed {
+        /// How often the message was repeated within the window so far
+        repeats: u32,
+    },
+
+    /// A message that exceeds a limit of a service, see [crate::limits]
+    #[error("{field} exceeds the limit of {service} ({actual} > {max})")]
+    LimitExceeded {
+        /// The name of the service
+        service: &'static str,
+        /// The part of the message that is too long, e.g. `content` or `embeds[0].title`
+        field: String,
+        /// The limit of the service
+        max: usize,
+        /// The length or number of items of the part
+        actual: usize,
+    },
+
+    /// Sending a message that was sent in several parts failed after some parts were delivered,
+    /// e.g. a [split](crate::limits) text
+    ///
+    /// The outbox skips the delivered parts when it sends the message again.
+    #[error("sent {sent} of {total} parts: {source}")]
+    PartiallySent {
+        /// How many parts the target received, the first ones of the message
+        sent: usize,
+        /// How many parts the message has
+        total: usize,
+        /// The error of the first part that was not sent
+        #[source]
+        source: Box<Error>,
+    },
+
     /// A catch all error when no other is applicable
     #[error("An Error occured: {0}")]
     Generic(String),
 }
+
+impl From<reqwest::Error> for Error {
+    fn from(mut e: reqwest::Error) -> Self {
+        if let Some(url) = e.url_mut() {
+            crate::redact::url_in_place(url);
+        }
+        Error::Reqwest(e)
+    }
+}
+
+impl Error {
+    /// Creates the error for a response of a service that rejected a request.
+    ///
+    /// The status decides the variant: `401` and `403` are [Error::Unauthorized], `404` is
+    /// [Error::NotFound], `413` is [Error::PayloadTooLarge], `429` is [Error::RateLimited] and
+    /// every other status is [Error::Api]. Own services can use this for their errors, too.
+    pub fn api(
+        service: &'static str,
+        status: Option<u16>,
+        code: Option<String>,
+        message: impl Into<String>,
+    ) -> Self {
+        let message = message.into();
+        match status {
+            Some(401 | 403) => Error::Unauthorized { service, message },
+            Some(404) => Error::NotFound { service, message },
+            Some(413) => Error::PayloadTooLarge { service, message },
+            Some(429) => Error::RateLimited { retry_after: None },
+            _ => Error::Api {
+                service,
+                status,
+                code,
+                message,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+
+    #[test]
+    fn test_api() {
+        let error = |status| Error::api("discord", status, None, "message");
+        assert!(matches!(error(Some(401)), Error::Unauthorized { .. }));
+        assert!(matches!(error(Some(403)), Error::Unauthorized { .. }));
+        assert!(matches!(error(Some(404)), Error::NotFound { .. }));
+        assert!(matches!(error(Some(413)), Error::PayloadTooLarge { .. }));
+        assert!(matches!(
+            error(Some(429)),
+            Error::RateLimited { retry_after: None }
+        ));
+        assert!(matches!(
+            error(Some(500)),
+            Error::Api {
+                status: Some(500),
+                ..
+            }
+        ));
+        assert_eq!(
+            "discord api error: message (status: None, code: None)",
+            error(None).to_string()
+        );
+    }
+}
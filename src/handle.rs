@@ -0,0 +1,187 @@
+//! Editing and deleting messages after they were sent.
+//!
+//! [Announce::announce_editable](crate::Announce::announce_editable) returns a [MessageHandle]
+//! for every target, which can replace the message later, e.g. to turn "deploy started" into
+//! "deploy finished":
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), announce_lib::Error> {
+//! use announce_lib::{Announce, Message};
+//!
+//! let announce = Announce::new().await?;
+//! let urls = vec![reqwest::Url::parse("discord://WEBHOOK_ID/WEBHOOK_TOKEN")?];
+//! let mut handles = announce
+//!     .announce_editable(urls, &Message::new("deploy started"))
+//!     .await;
+//!
+//! // ... deploying
+//!
+//! for handle in handles.iter_mut().flatten() {
+//!     handle.edit(&announce, &Message::new("deploy finished")).await?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! | Service    | Edit                                                  | Delete                               |
+//! |------------|-------------------------------------------------------|--------------------------------------|
+//! | Discord    | `PATCH /webhooks/{id}/{token}/messages/{message_id}`  | `DELETE` of the same url             |
+//! | Rocket.Chat| `chat.update`, only the text is replaced              | `chat.delete`                        |
+//! | D-Bus      | a new notification with `replaces_id`                 | `CloseNotification`                  |
+//!
+//! Files can't be edited. A message that was split into several messages because of its length
+//! has the handle of its first part.
+
+use std::fmt;
+
+use crate::{Announce, DeliveryReceipt, Error, Message};
+
+/// A message that was sent to a target and can be edited or deleted, see [the module](self)
+#[derive(Clone)]
+pub struct MessageHandle {
+    /// The url of the target with its secrets, which are needed to edit the message
+    url: reqwest::Url,
+    receipt: DeliveryReceipt,
+}
+
+impl MessageHandle {
+    /// Creates the handle of the message of `receipt` that was sent to `url`.
+    ///
+    /// Receipts without [message_id](DeliveryReceipt::message_id) can't be edited.
+    pub fn new(url: reqwest::Url, receipt: DeliveryReceipt) -> Self {
+        Self { url, receipt }
+    }
+
+    /// Returns the receipt of the last time the message was sent or edited
+    pub fn receipt(&self) -> &DeliveryReceipt {
+        &self.receipt
+    }
+
+    /// Returns the target without secrets
+    pub fn target(&self) -> &str {
+        &self.receipt.target
+    }
+
+    /// Replaces the message with `msg`.
+    ///
+    /// Returns an error if `msg` has attachments, files can't be edited.
+    pub async fn edit(
+        &mut self,
+        announce: &Announce,
+        msg: &Message<'_>,
+    ) -> Result<&DeliveryReceipt, Error> {
+        if !msg.attachments.is_empty() {
+            return Err(Error::Generic(String::from(
+                "files of a message can't be edited",
+            )));
+        }
+        let service = announce
+            .services()
+            .get(self.url.scheme())
+            .ok_or(Error::NoMatchingSchema)?;
+        let mut receipt = service
+            .edit(announce, &self.url, &self.receipt, msg)
+            .await?;
+
+        // not every service answers with the ids again
+        if receipt.message_id.is_none() {
+            receipt.message_id = self.receipt.message_id.take();
+        }
+        if receipt.channel_id.is_none() {
+            receipt.channel_id = self.receipt.channel_id.take();
+        }
+        self.receipt = receipt;
+        Ok(&self.receipt)
+    }
+
+    /// Removes the message from the target
+    pub async fn delete(self, announce: &Announce) -> Result<(), Error> {
+        let service = announce
+            .services()
+            .get(self.url.scheme())
+            .ok_or(Error::NoMatchingSchema)?;
+        service.delete(announce, &self.url, &self.receipt).await
+    }
+}
+
+impl fmt::Debug for MessageHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the receipt shows the target without secrets
+        f.debug_struct("MessageHandle")
+            .field("receipt", &self.receipt)
+            .finish()
+    }
+}
+
+/// Returns the id of the message of `receipt`, which is needed to edit or delete it
+///
+/// Services that implement [DynService::edit](crate::service::DynService::edit) can use this to
+/// return the same error for receipts without id.
+pub fn message_id(receipt: &DeliveryReceipt) -> Result<&str, Error> {
+    receipt
+        .message_id
+        .as_deref()
+        .ok_or_else(|| Error::Generic(format!("{} returned no id of the message", receipt.service)))
+}
+
+// the tests edit messages of Discord through a mock transport
+#[cfg(all(test, feature = "discord"))]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::transport::{MockResponse, MockTransport};
+    use crate::{Announce, Message};
+
+    #[tokio::test]
+    async fn test_edit_and_delete() {
+        let transport = Arc::new(MockTransport::new());
+        let announce = Announce::builder()
+            .transport(transport.clone())
+            .build()
+            .unwrap();
+        let urls = vec![
+            reqwest::Url::parse("discord://1/token").unwrap(),
+            reqwest::Url::parse("unknown://x").unwrap(),
+        ];
+
+        transport.push_response(MockResponse::json(
+            200,
+            serde_json::json!({ "id": "42", "channel_id": "7" }),
+        ));
+        let mut handles = announce
+            .announce_editable(urls, &Message::new("deploy started"))
+            .await;
+        assert!(matches!(handles[1], Err(crate::Error::NoMatchingSchema)));
+        let handle = handles.remove(0).unwrap();
+        assert_eq!("discord://1/***", handle.target());
+        assert!(!format!("{:?}", handle).contains("token"));
+
+        // the mock answers the edit without ids, so the ones of the first receipt are kept
+        let mut handle = handle;
+        let mut with_file = Message::new("deploy finished");
+        with_file
+            .attachments
+            .push("./sample_uploads/License.md".into());
+        assert!(handle.edit(&announce, &with_file).await.is_err());
+        let receipt = handle
+            .edit(&announce, &Message::new("deploy finished"))
+            .await
+            .unwrap();
+        assert_eq!(Some("42"), receipt.message_id.as_deref());
+        assert_eq!(Some("7"), receipt.channel_id.as_deref());
+
+        transport.push_response(MockResponse::new(204));
+        handle.delete(&announce).await.unwrap();
+
+        let requests = transport.requests();
+        assert_eq!(3, requests.len());
+        assert_eq!(reqwest::Method::PATCH, requests[1].method);
+        assert_eq!(
+            "https://discord.com/api/webhooks/1/token/messages/42",
+            requests[1].url.as_str()
+        );
+        assert_eq!("deploy finished", requests[1].json().unwrap()["content"]);
+        assert_eq!(reqwest::Method::DELETE, requests[2].method);
+        assert_eq!(requests[1].url, requests[2].url);
+    }
+}
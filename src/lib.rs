@@ -1,87 +1,771 @@
 #![warn(missing_docs)]
 #![doc = include_str!("../Readme.md")]
 
+pub mod attachment;
+#[cfg(feature = "batch")]
+pub mod batch;
+pub mod builder;
+#[cfg(feature = "config")]
+pub mod config;
 pub mod error;
+pub mod handle;
+pub mod limits;
+pub mod markup;
 pub mod message;
+#[cfg(feature = "outbox")]
+pub mod outbox;
+pub mod preview;
+pub mod receipt;
+pub mod redact;
+pub mod retry;
+pub mod richtext;
+pub mod routing;
+#[cfg(feature = "schedule")]
+pub mod schedule;
 pub mod service;
+pub mod template;
+pub mod throttle;
+pub mod transport;
 
+pub use attachment::Attachment;
+pub use builder::AnnounceBuilder;
 pub use error::Error;
+pub use handle::MessageHandle;
 pub use message::Hint;
 pub use message::Message;
+pub use preview::Preview;
+pub use receipt::DeliveryReceipt;
+pub use retry::RetryPolicy;
 
-// TODO merge with ServiceResult when async in traits is allowed
-/// Result that contains all results from every possible service
-#[derive(Debug)]
-pub enum ReturnType {
-    /// A Reqwest result
-    Reqwest(reqwest::Response),
-    /// A Dbus result
-    Dbus(u32),
-}
+use futures::stream::{self, StreamExt};
+
+/// Default number of targets that are served at the same time by [Announce::announce]
+pub const DEFAULT_CONCURRENCY: usize = 8;
 
 /// A struct to contain information to call services
 pub struct Announce {
     /// client for internet Api's
     pub client: reqwest::Client,
 
+    /// executes the requests to internet Api's
+    transport: std::sync::Arc<dyn transport::Transport>,
+
+    /// maximum number of targets that are served at the same time
+    concurrency: usize,
+
+    /// how failed requests to internet Api's are retried
+    retry: RetryPolicy,
+
+    /// split texts that exceed the limits of a service into several messages
+    split_long_messages: bool,
+
+    /// services that handle the schemes of urls
+    services: service::ServiceRegistry,
+
+    /// suppresses repeated messages
+    throttle: Option<throttle::Throttle>,
+
+    /// rules that decide the targets of [Announce::announce_routed]
+    routes: Vec<routing::Rule>,
+
+    #[cfg(feature = "config")]
+    /// named targets and groups
+    config: config::Config,
+
     #[cfg(feature = "dbus")]
-    /// connection for dbus
-    dbus_con: zbus::Connection,
+    /// connection for dbus; opened when it's needed for the first time
+    dbus_con: tokio::sync::OnceCell<zbus::Connection>,
+
+    #[cfg(feature = "outbox")]
+    /// durable queue of messages that are delivered in the background
+    outbox: Option<outbox::Outbox>,
 }
 
 impl Announce {
     /// Creates a Announce object for announcing
     pub async fn new() -> Result<Self, Error> {
-        //build client
-        let mut agent = reqwest::header::HeaderMap::new();
-        agent.insert(
-            reqwest::header::USER_AGENT,
-            format!("announce/{}", env!("CARGO_PKG_VERSION"))
-                .parse()
-                .unwrap(),
-        );
-        let client = reqwest::ClientBuilder::new()
-            .use_rustls_tls()
-            .default_headers(agent)
-            .build()?;
+        Self::builder().build()
+    }
 
-        #[cfg(feature = "dbus")]
-        let dbus_con = zbus::Connection::session().await?;
+    /// Returns a builder to create a Announce object with a custom configuration
+    pub fn builder() -> AnnounceBuilder {
+        AnnounceBuilder::new()
+    }
 
-        Ok(Self {
-            client,
-            #[cfg(feature = "dbus")]
-            dbus_con,
-        })
+    /// Returns the connection for dbus and opens a session connection if there is none yet
+    #[cfg(feature = "dbus")]
+    pub async fn dbus_connection(&self) -> Result<&zbus::Connection, Error> {
+        Ok(self
+            .dbus_con
+            .get_or_try_init(zbus::Connection::session)
+            .await?)
+    }
+
+    /// Sets how many targets are served at the same time.
+    ///
+    /// A limit of 0 is treated as 1, which sends to one target after another.
+    pub fn set_concurrency(&mut self, limit: usize) {
+        self.concurrency = limit.max(1);
+    }
+
+    /// Returns how many targets are served at the same time.
+    pub fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+
+    /// Returns the transport that executes the requests to internet Api's.
+    pub fn transport(&self) -> &dyn transport::Transport {
+        self.transport.as_ref()
+    }
+
+    /// Sets how failed requests to internet Api's are retried.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    /// Returns how failed requests to internet Api's are retried.
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// Sets whether texts that exceed the limits of a service are split into several messages.
+    ///
+    /// Otherwise such messages are not sent and [Error::LimitExceeded] is returned.
+    /// See [limits] for details.
+    pub fn set_split_long_messages(&mut self, split: bool) {
+        self.split_long_messages = split;
+    }
+
+    /// Returns whether texts that exceed the limits of a service are split into several messages.
+    pub fn split_long_messages(&self) -> bool {
+        self.split_long_messages
+    }
+
+    /// Sets the window in which repeats of a message to a target are suppressed.
+    ///
+    /// `None` sends every message. See [throttle] for details.
+    pub fn set_throttle(&mut self, window: Option<std::time::Duration>) {
+        self.throttle = window.map(throttle::Throttle::new);
+    }
+
+    /// Returns the throttle that suppresses repeated messages, if there is one.
+    pub fn throttle(&self) -> Option<&throttle::Throttle> {
+        self.throttle.as_ref()
+    }
+
+    /// Sends the summaries of the [throttle] whose windows closed.
+    ///
+    /// Every summary gets its own result. Summaries are also sent when the next message is
+    /// sent, calling this periodically sends them on time.
+    pub async fn send_summaries(&self) -> Vec<Result<DeliveryReceipt, Error>> {
+        service::send_summaries(self).await
+    }
+
+    /// Registers a service for all of its schemes.
+    ///
+    /// A service that was registered for the same scheme before is replaced.
+    pub fn register_service<S: service::DynService + 'static>(&mut self, service: S) {
+        self.services.register(service);
+    }
+
+    /// Returns the url without secrets, as the service of its scheme shows it.
+    ///
+    /// Urls of unknown schemes are redacted as described in [redact].
+    pub fn redact(&self, url: &reqwest::Url) -> String {
+        match self.services.get(url.scheme()) {
+            Some(service) => service.redact(url),
+            None => redact::url(url),
+        }
+    }
+
+    /// Returns the services that handle the schemes of urls.
+    pub fn services(&self) -> &service::ServiceRegistry {
+        &self.services
+    }
+
+    /// Returns the services that handle the schemes of urls for modification.
+    pub fn services_mut(&mut self) -> &mut service::ServiceRegistry {
+        &mut self.services
+    }
+
+    /// Sets the rules that decide the targets of [Announce::announce_routed].
+    pub fn set_routes(&mut self, routes: Vec<routing::Rule>) {
+        self.routes = routes;
+    }
+
+    /// Returns the rules that decide the targets of [Announce::announce_routed].
+    ///
+    /// The rules of the configuration are not contained.
+    pub fn routes(&self) -> &[routing::Rule] {
+        &self.routes
+    }
+
+    /// Sets the named targets and groups used by [Announce::announce_to].
+    #[cfg(feature = "config")]
+    pub fn set_config(&mut self, config: config::Config) {
+        self.config = config;
+    }
+
+    /// Returns the named targets and groups used by [Announce::announce_to].
+    #[cfg(feature = "config")]
+    pub fn config(&self) -> &config::Config {
+        &self.config
+    }
+
+    /// Returns the outbox of messages that are delivered in the background, if there is one.
+    #[cfg(feature = "outbox")]
+    pub fn outbox(&self) -> Option<&outbox::Outbox> {
+        self.outbox.as_ref()
+    }
+
+    /// Puts a message for multiple services into the [outbox] and returns its id.
+    ///
+    /// The message is stored before this returns and delivered later by an [outbox::Worker]
+    /// or [outbox::Outbox::flush]. Returns an error if there is no outbox or if a url has a
+    /// scheme without service.
+    #[cfg(feature = "outbox")]
+    pub async fn enqueue(
+        &self,
+        urls: Vec<reqwest::Url>,
+        msg: &Message<'_>,
+    ) -> Result<String, Error> {
+        let outbox = self
+            .outbox
+            .as_ref()
+            .ok_or_else(|| Error::Config(String::from("no outbox is configured")))?;
+        if urls
+            .iter()
+            .any(|url| self.services.get(url.scheme()).is_none())
+        {
+            return Err(Error::NoMatchingSchema);
+        }
+        outbox.push(urls, msg).await
+    }
+
+    /// Sends a message to a named target or to all targets of a group of the
+    /// [configuration](config::Config).
+    ///
+    /// Returns an error if the name is unknown. Otherwise every target gets its own result,
+    /// paired with the name of the target, just like with [Announce::announce].
+    #[cfg(feature = "config")]
+    pub async fn announce_to(
+        &self,
+        name: &str,
+        msg: &Message<'_>,
+    ) -> Result<Vec<(String, Result<DeliveryReceipt, Error>)>, Error> {
+        let targets = self.config.resolve(name)?;
+        let messages: Vec<_> = targets
+            .iter()
+            .map(|(_, target)| target.defaults.apply(msg))
+            .collect();
+
+        let sends: Vec<_> = targets
+            .iter()
+            .zip(messages.iter())
+            .map(|((_, target), msg)| service::decide_service(self, &target.url, msg))
+            .collect();
+        let results: Vec<_> = stream::iter(sends)
+            .buffered(self.concurrency)
+            .collect()
+            .await;
+
+        Ok(targets
+            .iter()
+            .map(|(name, _)| String::from(*name))
+            .zip(results)
+            .collect())
+    }
+
+    /// Sends a message to the targets of the [routing rules](routing) that match it.
+    ///
+    /// Returns [Error::NoRoute] if no rule matches or an error if a target is neither a url
+    /// nor a name of the configuration. Otherwise every target gets its own result, paired
+    /// with its name or its url without secrets, just like with [Announce::announce_to].
+    pub async fn announce_routed(
+        &self,
+        msg: &Message<'_>,
+    ) -> Result<Vec<(String, Result<DeliveryReceipt, Error>)>, Error> {
+        let targets = routing::resolve(self, msg)?;
+
+        let sends: Vec<_> = targets
+            .iter()
+            .map(|(_, url, msg)| service::decide_service(self, url, msg))
+            .collect();
+        let results: Vec<_> = stream::iter(sends)
+            .buffered(self.concurrency)
+            .collect()
+            .await;
+
+        Ok(targets
+            .into_iter()
+            .map(|(name, _, _)| name)
+            .zip(results)
+            .collect())
     }
 
     /// Sends the same messages to multiple services.
     ///
-    /// If an error is encountered while sending a message the following urls that follow
-    /// will be canceled.
+    /// The targets are served concurrently, limited by [Announce::concurrency].
+    /// Every target gets its own result, which is at the same position as its url in `urls`.
+    /// An error of one target does neither cancel nor block the other targets.
     pub async fn announce(
         &self,
         urls: Vec<reqwest::Url>,
         msg: &Message<'_>,
-    ) -> Result<Vec<ReturnType>, Error> {
-        //build requests for each given target
-        let mut results = vec![];
-        for url in urls {
-            results.push(service::decide_service(self, &url, msg).await?);
+    ) -> Vec<Result<DeliveryReceipt, Error>> {
+        let targets = urls.into_iter().map(|url| (url, 0)).collect();
+        self.announce_parts(targets, msg).await
+    }
+
+    /// Sends the same message to multiple services like [Announce::announce], but skips the
+    /// parts of the message that a target already received, see [Error::PartiallySent]
+    pub(crate) async fn announce_parts(
+        &self,
+        targets: Vec<(reqwest::Url, usize)>,
+        msg: &Message<'_>,
+    ) -> Vec<Result<DeliveryReceipt, Error>> {
+        // the futures are created up front, a closure in the stream would keep the returned
+        // future from being spawned (rust-lang/rust#64552)
+        let sends: Vec<_> = targets
+            .iter()
+            .map(|(url, sent)| service::decide_parts(self, url, msg, *sent))
+            .collect();
+        stream::iter(sends)
+            .buffered(self.concurrency)
+            .collect()
+            .await
+    }
+
+    /// Sends the same messages to multiple services like [Announce::announce] and returns
+    /// [handles](handle) to edit or delete the messages later
+    pub async fn announce_editable(
+        &self,
+        urls: Vec<reqwest::Url>,
+        msg: &Message<'_>,
+    ) -> Vec<Result<MessageHandle, Error>> {
+        let results = self.announce(urls.clone(), msg).await;
+        urls.into_iter()
+            .zip(results)
+            .map(|(url, result)| result.map(|receipt| MessageHandle::new(url, receipt)))
+            .collect()
+    }
+
+    /// Describes what [Announce::announce] would send to multiple services, without sending it.
+    ///
+    /// Every target gets the [previews](preview) of its requests, or an error if the message
+    /// can't be sent to it. A target can get more than one request, e.g. if the message is split
+    /// or has several files.
+    pub async fn dry_run(
+        &self,
+        urls: Vec<reqwest::Url>,
+        msg: &Message<'_>,
+    ) -> Vec<Result<Vec<Preview>, Error>> {
+        let previews: Vec<_> = urls
+            .iter()
+            .map(|url| service::decide_preview(self, url, msg))
+            .collect();
+        stream::iter(previews)
+            .buffered(self.concurrency)
+            .collect()
+            .await
+    }
+
+    /// Renders a [template](template) for every service and sends it to multiple services.
+    ///
+    /// The values of `ctx` are escaped for the [Markup](markup::Markup) of the service of each url.
+    /// Returns an error if the template can't be rendered. Otherwise every target gets its own
+    /// result, just like with [Announce::announce].
+    pub async fn announce_template(
+        &self,
+        urls: Vec<reqwest::Url>,
+        template: &Message<'_>,
+        ctx: &template::Context,
+    ) -> Result<Vec<Result<DeliveryReceipt, Error>>, Error> {
+        let markups: Vec<_> = urls
+            .iter()
+            .map(|url| {
+                self.services
+                    .get(url.scheme())
+                    .map_or(markup::Markup::Plain, |service| service.markup())
+            })
+            .collect();
+        let mut rendered = std::collections::HashMap::new();
+        for markup in &markups {
+            if !rendered.contains_key(markup) {
+                rendered.insert(*markup, template.render(ctx, *markup)?);
+            }
         }
 
-        Ok(results)
+        let sends: Vec<_> = urls
+            .iter()
+            .zip(markups)
+            .map(|(url, markup)| service::decide_service(self, url, &rendered[&markup]))
+            .collect();
+        Ok(stream::iter(sends)
+            .buffered(self.concurrency)
+            .collect()
+            .await)
     }
 
     /// Sends the same message to multiple services but ignores errors.
     ///
     /// If a services produces an error it will be logged and ignored.
     pub async fn announce_ignore_errors(&self, urls: Vec<reqwest::Url>, msg: &Message<'_>) {
-        for url in urls {
-            match service::decide_service(self, &url, msg).await {
+        let results = self.announce(urls.clone(), msg).await;
+        for (url, result) in urls.iter().zip(results) {
+            match result {
+                Ok(receipt) if !receipt.success => log::warn!(
+                    "{} did not accept the message (status: {:?})",
+                    receipt.target,
+                    receipt.status
+                ),
                 Ok(_) => {}
-                Err(e) => log::warn!("encountered an error in {}: {}", url, e),
+                Err(e @ Error::Suppressed { .. }) => {
+                    log::debug!("{}: {}", self.redact(url), e)
+                }
+                Err(e) => log::warn!("encountered an error in {}: {}", self.redact(url), e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// A service that fails for the host "fail" and counts parallel calls
+    #[derive(Default)]
+    struct Counting {
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl crate::service::DynService for Arc<Counting> {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        fn schema(&self) -> Vec<&'static str> {
+            vec!["counting"]
+        }
+
+        async fn notify(
+            &self,
+            _announce: &crate::Announce,
+            url: &reqwest::Url,
+            _msg: &crate::Message<'_>,
+        ) -> Result<crate::DeliveryReceipt, crate::Error> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+
+            match url.host_str() {
+                Some("fail") => Err(crate::Error::Generic(String::from("failed"))),
+                host => Ok(crate::DeliveryReceipt::new(
+                    self.name(),
+                    self.redact(url),
+                    chrono::Utc::now(),
+                    host.map(String::from),
+                )),
+            }
+        }
+    }
+
+    fn urls(urls: &[&str]) -> Vec<reqwest::Url> {
+        urls.iter()
+            .map(|url| reqwest::Url::parse(url).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_announce_keeps_order_and_isolates_errors() {
+        let counting = Arc::new(Counting::default());
+        let announce = crate::Announce::builder()
+            .register_service(counting.clone())
+            .build()
+            .unwrap();
+
+        let msg = crate::Message::new("test");
+        let targets = urls(&[
+            "counting://a",
+            "counting://fail",
+            "unknown://x",
+            "counting://b",
+        ]);
+        let results = announce.announce(targets, &msg).await;
+
+        assert_eq!(4, results.len());
+        assert_eq!(
+            Some("a"),
+            results[0].as_ref().unwrap().message_id.as_deref()
+        );
+        assert!(matches!(results[1], Err(crate::Error::Generic(_))));
+        assert!(matches!(results[2], Err(crate::Error::NoMatchingSchema)));
+        assert_eq!(
+            Some("b"),
+            results[3].as_ref().unwrap().message_id.as_deref()
+        );
+    }
+
+    #[cfg(feature = "config")]
+    #[tokio::test]
+    async fn test_announce_to() {
+        let config = crate::config::Config::from_toml_str(
+            r#"
+            [targets]
+            a = "counting://a"
+            broken = "counting://fail"
+            [groups]
+            all = ["a", "broken"]
+            "#,
+        )
+        .unwrap();
+        let announce = crate::Announce::builder()
+            .register_service(Arc::new(Counting::default()))
+            .config(config)
+            .build()
+            .unwrap();
+
+        let msg = crate::Message::new("test");
+        let results = announce.announce_to("all", &msg).await.unwrap();
+        assert_eq!("a", results[0].0);
+        assert!(results[0].1.is_ok());
+        assert_eq!("broken", results[1].0);
+        assert!(results[1].1.is_err());
+
+        assert!(matches!(
+            announce.announce_to("nobody", &msg).await,
+            Err(crate::Error::UnknownTarget(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_announce_template() {
+        let announce = crate::Announce::builder()
+            .register_service(Arc::new(Counting::default()))
+            .build()
+            .unwrap();
+
+        let template = crate::Message::new("{{ service }} is down");
+        let ctx = crate::template::Context::new().with("service", "db");
+        let results = announce
+            .announce_template(urls(&["counting://a", "counting://b"]), &template, &ctx)
+            .await
+            .unwrap();
+        assert!(results.iter().all(Result::is_ok));
+
+        let missing = crate::template::Context::new();
+        assert!(matches!(
+            announce
+                .announce_template(urls(&["counting://a"]), &template, &missing)
+                .await,
+            Err(crate::Error::Template(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let announce = crate::Announce::builder()
+            .register_service(Arc::new(Counting::default()))
+            .build()
+            .unwrap();
+
+        let msg = crate::Message::new("test");
+        let results = announce
+            .dry_run(urls(&["counting://a", "unknown://x"]), &msg)
+            .await;
+        // services without support for dry runs return an error
+        assert!(matches!(results[0], Err(crate::Error::Generic(_))));
+        assert!(matches!(results[1], Err(crate::Error::NoMatchingSchema)));
+    }
+
+    #[tokio::test]
+    async fn test_throttle() {
+        let announce = crate::Announce::builder()
+            .register_service(Arc::new(Counting::default()))
+            .throttle(std::time::Duration::from_millis(50))
+            .build()
+            .unwrap();
+
+        let msg = crate::Message::new("test");
+        let targets = urls(&["counting://a"]);
+        assert!(announce.announce(targets.clone(), &msg).await[0].is_ok());
+        for repeats in 1..=3 {
+            assert!(matches!(
+                announce.announce(targets.clone(), &msg).await[0],
+                Err(crate::Error::Suppressed { repeats: r }) if r == repeats
+            ));
+        }
+        assert!(announce.send_summaries().await.is_empty());
+
+        tokio::time::sleep(std::time::Duration::from_millis(60)).await;
+        let summaries = announce.send_summaries().await;
+        assert_eq!(1, summaries.len());
+        assert_eq!(
+            Some("a"),
+            summaries[0].as_ref().unwrap().message_id.as_deref()
+        );
+        assert!(announce.announce(targets, &msg).await[0].is_ok());
+
+        // failed sends are not repeats
+        let targets = urls(&["counting://fail"]);
+        for _ in 0..2 {
+            assert!(matches!(
+                announce.announce(targets.clone(), &msg).await[0],
+                Err(crate::Error::Generic(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_announce_routed() {
+        use crate::message::Severity;
+        use crate::routing::Rule;
+
+        let mut announce = crate::Announce::builder()
+            .register_service(Arc::new(Counting::default()))
+            .route(
+                Rule::new(["counting://pager", "counting://chat"])
+                    .with_min_severity(Severity::Error)
+                    .stop(),
+            )
+            .route(Rule::new(["counting://chat"]).with_tag("deploy"))
+            .route(Rule::new(["counting://log", "counting://chat"]).with_text("*finished"))
+            .build()
+            .unwrap();
+
+        let mut critical = crate::Message::new("deploy finished");
+        critical
+            .hints
+            .push(crate::Hint::Severity(Severity::Critical));
+        critical.hints.push(crate::Hint::Tag("deploy".into()));
+        let results = announce.announce_routed(&critical).await.unwrap();
+        let targets: Vec<_> = results.iter().map(|(target, _)| target.as_str()).collect();
+        assert_eq!(vec!["counting://pager", "counting://chat"], targets);
+
+        let mut info = crate::Message::new("deploy finished");
+        info.hints.push(crate::Hint::Tag("deploy".into()));
+        let results = announce.announce_routed(&info).await.unwrap();
+        let targets: Vec<_> = results.iter().map(|(target, _)| target.as_str()).collect();
+        assert_eq!(vec!["counting://chat", "counting://log"], targets);
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+
+        assert!(matches!(
+            announce
+                .announce_routed(&crate::Message::new("started"))
+                .await,
+            Err(crate::Error::NoRoute)
+        ));
+
+        announce.set_routes(vec![Rule::new(["oncall"])]);
+        assert!(matches!(
+            announce.announce_routed(&info).await,
+            Err(crate::Error::UnknownTarget(name)) if name == "oncall"
+        ));
+    }
+
+    #[cfg(feature = "config")]
+    #[tokio::test]
+    async fn test_announce_routed_to_config() {
+        let config = crate::config::Config::from_toml_str(
+            r#"
+            [targets]
+            pager = "counting://pager"
+            [targets.chat]
+            url = "counting://chat"
+            link = "https://status.example.com"
+
+            [groups]
+            oncall = ["pager", "chat"]
+
+            [[routes]]
+            targets = ["oncall", "counting://chat", "counting://log"]
+            "#,
+        )
+        .unwrap();
+        let announce = crate::Announce::builder()
+            .register_service(Arc::new(Counting::default()))
+            .config(config)
+            .build()
+            .unwrap();
+
+        let results = announce
+            .announce_routed(&crate::Message::new("test"))
+            .await
+            .unwrap();
+        let targets: Vec<_> = results.iter().map(|(target, _)| target.as_str()).collect();
+        assert_eq!(vec!["pager", "chat", "counting://log"], targets);
+    }
+
+    #[tokio::test]
+    async fn test_announce_concurrency_limit() {
+        let counting = Arc::new(Counting::default());
+        let announce = crate::Announce::builder()
+            .register_service(counting.clone())
+            .concurrency(2)
+            .build()
+            .unwrap();
+
+        let msg = crate::Message::new("test");
+        let targets = urls(&[
+            "counting://1",
+            "counting://2",
+            "counting://3",
+            "counting://4",
+        ]);
+        let results = announce.announce(targets, &msg).await;
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(2, counting.max_running.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_futures_can_be_spawned() {
+        let announce = Arc::new(
+            crate::Announce::builder()
+                .register_service(Arc::new(Counting::default()))
+                .route(crate::routing::Rule::new(["counting://routed"]))
+                .build()
+                .unwrap(),
+        );
+        let msg = crate::Message::new("test");
+
+        // fails to compile if a future isn't Send
+        let mut tasks = vec![];
+        tasks.push(tokio::spawn({
+            let (announce, msg) = (announce.clone(), msg.clone());
+            async move { announce.announce(urls(&["counting://a"]), &msg).await.len() }
+        }));
+        tasks.push(tokio::spawn({
+            let (announce, msg) = (announce.clone(), msg.clone());
+            async move { announce.dry_run(urls(&["counting://a"]), &msg).await.len() }
+        }));
+        tasks.push(tokio::spawn({
+            let (announce, msg) = (announce.clone(), msg.clone());
+            async move {
+                let ctx = crate::template::Context::new();
+                let results = announce.announce_template(urls(&["counting://a"]), &msg, &ctx);
+                results.await.unwrap().len()
             }
+        }));
+        tasks.push(tokio::spawn({
+            let (announce, msg) = (announce.clone(), msg.clone());
+            async move { announce.announce_routed(&msg).await.unwrap().len() }
+        }));
+        #[cfg(feature = "config")]
+        tasks.push(tokio::spawn({
+            let (announce, msg) = (announce.clone(), msg.clone());
+            async move {
+                let results = announce.announce_to("nobody", &msg).await;
+                usize::from(results.is_err())
+            }
+        }));
+
+        for task in tasks {
+            assert_eq!(1, task.await.unwrap());
         }
     }
 }
@@ -0,0 +1,206 @@
+//! Limits of services for the size of messages.
+//!
+//! Messages are checked against the limits of a service before they are sent, instead of
+//! letting the service reject them. If a limit is exceeded
+//! [Error::LimitExceeded](crate::Error::LimitExceeded) describes which one.
+//!
+//! Texts that are too long can be split into several messages, which are sent one after another:
+//!
+//! ```no_run
+//! # fn main() -> Result<(), announce_lib::Error> {
+//! let announce = announce_lib::Announce::builder()
+//!     .split_long_messages(true)
+//!     .build()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Only the text of a message is split. Limits of other parts, e.g. of embeds, are still
+//! returned as errors.
+//!
+//! The parts are not sent atomically. If a part fails after others were delivered,
+//! [Error::PartiallySent](crate::Error::PartiallySent) tells how many parts the target received;
+//! sending the message again sends those parts twice, except from the outbox, which skips them.
+
+/// The line that closes a code block
+const CLOSE_FENCE: &str = "\n```";
+
+/// Returns the length of a text as it is counted by services
+pub fn length(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// Returns [Error::LimitExceeded](crate::Error::LimitExceeded) if `actual` is greater than `max`
+///
+/// `field` names the part of the message that is checked. Own services can use this to check
+/// their limits, too.
+pub fn check(
+    service: &'static str,
+    field: impl std::fmt::Display,
+    actual: usize,
+    max: usize,
+) -> Result<(), crate::Error> {
+    if actual > max {
+        return Err(crate::Error::LimitExceeded {
+            service,
+            field: field.to_string(),
+            max,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Splits a text into chunks of at most `max` characters.
+///
+/// Texts are split between lines if possible, otherwise between words and only as a last
+/// resort within a word. A code block that is split is closed at the end of a chunk and opened
+/// again, with the same language, at the start of the next one. If `max` leaves no room for the
+/// fences of a code block, the text is split without them. A `max` of 0 is treated as 1.
+pub fn split_text(text: &str, max: usize) -> Vec<String> {
+    let max = max.max(1);
+    if length(text) <= max {
+        return vec![String::from(text)];
+    }
+
+    let chunks = split_fenced(text, max, true);
+    match chunks.iter().all(|chunk| length(chunk) <= max) {
+        true => chunks,
+        false => split_fenced(text, max, false),
+    }
+}
+
+/// Splits a text like [split_text], closing and opening split code blocks if `fences` is set
+fn split_fenced(text: &str, max: usize, fences: bool) -> Vec<String> {
+    let mut chunks = vec![];
+    let mut chunk = String::new();
+    // the line that opened the code block the current line is in
+    let mut fence: Option<&str> = None;
+    for line in text.split_inclusive('\n') {
+        let open_after = match (fences && line.trim_start().starts_with("```"), fence) {
+            (true, Some(_)) => None,
+            (true, None) => Some(line.trim_end()),
+            (false, fence) => fence,
+        };
+        // a chunk that ends within a code block needs room to close it
+        let reserve = open_after.map_or(0, |_| CLOSE_FENCE.len());
+        // pieces of a long line within a code block have to fit between the fences
+        let fences = fence.map_or(0, |open| length(open) + 1 + CLOSE_FENCE.len());
+        let room = max.saturating_sub(fences).max(1);
+
+        for piece in split_line(line, room) {
+            if !chunk.is_empty() && length(&chunk) + length(piece) + reserve > max {
+                finish_chunk(&mut chunks, &mut chunk, fence);
+                if let Some(open) = fence {
+                    chunk.push_str(open);
+                    chunk.push('\n');
+                }
+            }
+            chunk.push_str(piece);
+        }
+        fence = open_after;
+    }
+    finish_chunk(&mut chunks, &mut chunk, None);
+
+    chunks
+}
+
+/// Moves a chunk to the finished ones and closes its open code block
+fn finish_chunk(chunks: &mut Vec<String>, chunk: &mut String, fence: Option<&str>) {
+    let mut finished = std::mem::take(chunk);
+    finished.truncate(finished.trim_end().len());
+    if finished.is_empty() {
+        return;
+    }
+    if fence.is_some() {
+        finished.push_str(CLOSE_FENCE);
+    }
+    chunks.push(finished);
+}
+
+/// Splits a line into pieces of at most `room` characters after whitespace if possible
+fn split_line(line: &str, room: usize) -> Vec<&str> {
+    let mut pieces = vec![];
+    let mut rest = line;
+    while length(rest) > room {
+        let end = rest.char_indices().nth(room).map_or(rest.len(), |(i, _)| i);
+        let cut = rest[..end]
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map_or(end, |(i, c)| i + c.len_utf8());
+        pieces.push(&rest[..cut]);
+        rest = &rest[cut..];
+    }
+    pieces.push(rest);
+
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, length, split_text};
+
+    #[test]
+    fn test_split_lines_and_words() {
+        assert_eq!(vec!["short"], split_text("short", 10));
+        assert_eq!(vec!["aaa\nbbb", "ccc"], split_text("aaa\nbbb\nccc", 8));
+        assert_eq!(
+            vec!["one two", "three", "four"],
+            split_text("one two three four", 9)
+        );
+        assert_eq!(vec!["abcd", "efgh", "ij"], split_text("abcdefghij", 4));
+        assert_eq!(vec!["äöü", "ß"], split_text("äöüß", 3));
+    }
+
+    #[test]
+    fn test_split_code_blocks() {
+        let text = "intro\n```rust\nlet a = 1;\nlet b = 2;\nlet c = 3;\n```\noutro";
+        let chunks = split_text(text, 36);
+        assert_eq!(
+            vec![
+                "intro\n```rust\nlet a = 1;\n```",
+                "```rust\nlet b = 2;\nlet c = 3;\n```",
+                "outro"
+            ],
+            chunks
+        );
+        for chunk in &chunks {
+            assert!(length(chunk) <= 36);
+            assert_eq!(0, chunk.matches("```").count() % 2);
+        }
+    }
+
+    #[test]
+    fn test_split_without_room_for_fences() {
+        let text = "```rust\nlet a = 1;\n```";
+        for max in 1..length(text) {
+            let chunks = split_text(text, max);
+            assert!(chunks.iter().all(|chunk| length(chunk) <= max), "{}", max);
+        }
+        // the fences alone are longer than 5 characters
+        assert_eq!(
+            vec!["```ru", "st", "let", "a =", "1;", "```"],
+            split_text(text, 5)
+        );
+        assert_eq!(vec!["a", "b"], split_text("ab", 0));
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(check("discord", "content", 2000, 2000).is_ok());
+        match check("discord", format_args!("embeds[{}].title", 0), 300, 256) {
+            Err(crate::Error::LimitExceeded {
+                service,
+                field,
+                max,
+                actual,
+            }) => {
+                assert_eq!("discord", service);
+                assert_eq!("embeds[0].title", field);
+                assert_eq!((256, 300), (max, actual));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! The markup languages of the services.
+
+use std::borrow::Cow;
+
+/// The markup a service uses to format text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Markup {
+    /// Text is shown as it is
+    Plain,
+    /// The Markdown of Discord
+    Discord,
+    /// The markdown dialect of Rocket.Chat
+    RocketChat,
+    /// The small subset of HTML supported by desktop notifications
+    Dbus,
+}
+
+impl Markup {
+    /// Escapes text, so it is shown literally instead of being interpreted as markup
+    ///
+    /// The text is only copied if it contains something that has to be escaped.
+    pub fn escape(self, text: &str) -> Cow<'_, str> {
+        match self {
+            Markup::Plain => Cow::Borrowed(text),
+            Markup::Discord => escape_with(text, |c| match c {
+                '\\' | '*' | '_' | '~' | '`' | '|' | '>' | '#' | '[' | ']' => Some(Escaped::Char),
+                _ => None,
+            }),
+            Markup::RocketChat => escape_with(text, |c| match c {
+                '\\' | '*' | '_' | '~' | '`' | '>' | '#' | '[' | ']' => Some(Escaped::Char),
+                _ => None,
+            }),
+            Markup::Dbus => escape_with(text, |c| match c {
+                '&' => Some(Escaped::Str("&amp;")),
+                '<' => Some(Escaped::Str("&lt;")),
+                '>' => Some(Escaped::Str("&gt;")),
+                _ => None,
+            }),
+        }
+    }
+}
+
+/// How a single character is escaped
+enum Escaped {
+    /// The character is prefixed with a backslash
+    Char,
+    /// The character is replaced
+    Str(&'static str),
+}
+
+fn escape_with(text: &str, escape: impl Fn(char) -> Option<Escaped>) -> Cow<'_, str> {
+    if !text.chars().any(|c| escape(c).is_some()) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut escaped = String::with_capacity(text.len() + 8);
+    for c in text.chars() {
+        match escape(c) {
+            Some(Escaped::Char) => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            Some(Escaped::Str(s)) => escaped.push_str(s),
+            None => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Markup;
+
+    #[test]
+    fn test_escape() {
+        let text = "*bold* <b>&</b> `code`";
+        assert_eq!(text, Markup::Plain.escape(text));
+        assert_eq!(
+            r"\*bold\* <b\>&</b\> \`code\` a\|b",
+            Markup::Discord.escape(&format!("{} a|b", text))
+        );
+        assert_eq!(
+            r"\*bold\* <b\>&</b\> \`code\` a|b",
+            Markup::RocketChat.escape(&format!("{} a|b", text))
+        );
+        assert_eq!(
+            "*bold* &lt;b&gt;&amp;&lt;/b&gt; `code`",
+            Markup::Dbus.escape(text)
+        );
+
+        // nothing to escape, nothing to copy
+        assert!(matches!(
+            Markup::Discord.escape("plain text"),
+            std::borrow::Cow::Borrowed(_)
+        ));
+    }
+}
@@ -1,36 +1,474 @@
 //! A Subset of Message types that are supported by most services.
+//!
+//! # Serialization
+//! [Message] and [Hint] can be serialized and deserialized with serde.
+//! Every field of a message is optional and hints are written as a map with a single key that
+//! is the name of the hint in snake case.
+//!
+//! ```json
+//! {
+//!     "text": "Deployment finished",
+//!     "hints": [
+//!         { "link": "https://example.com/deployments/42" },
+//!         { "description": "All services are up" },
+//!         { "title": "Deployment" },
+//!         { "color": 3066993 },
+//!         { "timestamp": "2022-12-24T18:00:00Z" },
+//!         { "field": { "name": "Version", "value": "1.2.0", "inline": true } },
+//!         { "urgency": "low" },
+//!         { "severity": "info" },
+//!         { "tag": "deploy" }
+//!     ],
+//!     "attachments": ["./deploy.log", "./screenshot.png"]
+//! }
+//! ```
+//!
+//! A [rich text](crate::richtext::Document) is written as a list of blocks under `rich_text`:
+//!
+//! ```json
+//! {
+//!     "rich_text": [
+//!         { "paragraph": [{ "bold": [{ "text": "Deployment" }] }, { "text": " finished" }] },
+//!         { "code_block": { "language": "sh", "code": "./deploy.sh" } }
+//!     ]
+//! }
+//! ```
+
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::attachment::Attachment;
+use crate::richtext::Document;
+use crate::template::{self, Context, Markup};
 
 /// A Subset of Message types that are supported by all services.
 /// Message should be used in conjunction with multiple services. It's a feature subset of
 /// messages to the messages the supported services provide.
 /// That means the struct Message from the module of a service provides more customization for that
 /// service.
-#[derive(Debug, Default)]
+///
+/// The message may borrow its contents; use [Message::into_owned] to get a message that can be
+/// moved into other tasks or queues.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
 pub struct Message<'a> {
     /// Text to send
-    pub text: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<Cow<'a, str>>,
+    /// Formatted text to send; services render it in their markup and prefer it over `text`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rich_text: Option<Document<'a>>,
     /// Some more special infos that vary by service
+    #[serde(skip_serializing_if = "Vec::is_empty")]
     pub hints: Vec<Hint<'a>>,
-    /// A path to a file which can be send
-    pub file_path: Option<&'a str>,
+    /// Files which are sent with the message
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment<'a>>,
 }
 
 impl<'a> Message<'a> {
     /// Constructs a simple Message for sending to services
-    pub fn new(text: &'a str) -> Self {
+    pub fn new(text: impl Into<Cow<'a, str>>) -> Self {
         Self {
-            text: Some(text),
+            text: Some(text.into()),
             ..Default::default()
         }
     }
+
+    /// Converts the message into one that doesn't borrow any data
+    pub fn into_owned(self) -> Message<'static> {
+        Message {
+            text: self.text.map(owned),
+            rich_text: self.rich_text.map(Document::into_owned),
+            hints: self.hints.into_iter().map(Hint::into_owned).collect(),
+            attachments: self
+                .attachments
+                .into_iter()
+                .map(Attachment::into_owned)
+                .collect(),
+        }
+    }
+
+    /// Combines several messages into one, e.g. to send a batch of them at once.
+    ///
+    /// Every message becomes a [Hint::Field] named after its title or its position, with its
+    /// text shortened to [MAX_COMBINED_FIELD] characters. Rich texts are combined as plain text.
+    /// The combined message has the highest urgency and severity and all attachments of the
+    /// messages. Services split batches whose combined message exceeds their limits, e.g. the
+    /// total length of the embeds of Discord.
+    pub fn combine(messages: &[Message<'_>]) -> Message<'static> {
+        let mut combined = Message::new(count(messages.len()));
+        let mut urgency = None;
+        let mut severity = None;
+        for (i, msg) in messages.iter().enumerate() {
+            let mut name = None;
+            for hint in &msg.hints {
+                match hint {
+                    Hint::Title(title) => name = Some(owned(title.clone())),
+                    Hint::Urgency(u) => urgency = urgency.max(Some(*u)),
+                    Hint::Severity(s) => severity = severity.max(Some(*s)),
+                    _ => {}
+                }
+            }
+            let text = msg.text_for(Markup::Plain).unwrap_or(Cow::Borrowed("-"));
+            let value: String = match crate::limits::length(&text) > MAX_COMBINED_FIELD {
+                true => text
+                    .chars()
+                    .take(MAX_COMBINED_FIELD - 1)
+                    .chain(std::iter::once('…'))
+                    .collect(),
+                false => text.into_owned(),
+            };
+            combined.hints.push(Hint::Field {
+                name: name.unwrap_or_else(|| Cow::Owned(format!("#{}", i + 1))),
+                value: Cow::Owned(value),
+                inline: false,
+            });
+            combined
+                .attachments
+                .extend(msg.attachments.iter().cloned().map(Attachment::into_owned));
+        }
+        if let Some(urgency) = urgency {
+            combined.hints.push(Hint::Urgency(urgency));
+        }
+        if let Some(severity) = severity {
+            combined.hints.push(Hint::Severity(severity));
+        }
+
+        combined
+    }
+
+    /// Returns the text of the message in the markup of a service.
+    ///
+    /// The [rich text](Message::rich_text) is rendered if there is one, otherwise the plain
+    /// text is returned as it is.
+    pub fn text_for(&self, markup: Markup) -> Option<Cow<'_, str>> {
+        match (&self.rich_text, &self.text) {
+            (Some(doc), _) => Some(Cow::Owned(doc.render(markup))),
+            (None, Some(text)) => Some(Cow::Borrowed(text)),
+            (None, None) => None,
+        }
+    }
+
+    /// Returns the severity of the [Hint::Severity], or [Severity::Info] without one
+    pub fn severity(&self) -> Severity {
+        self.hints
+            .iter()
+            .find_map(|hint| match hint {
+                Hint::Severity(severity) => Some(*severity),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the [tags](Hint::Tag) of the message
+    pub fn tags(&self) -> impl Iterator<Item = &str> {
+        self.hints.iter().filter_map(|hint| match hint {
+            Hint::Tag(tag) => Some(tag.as_ref()),
+            _ => None,
+        })
+    }
+
+    /// Fills the placeholders of this message with the variables of `ctx`.
+    ///
+    /// The values in the text and the description are escaped for `markup`; links and the paths
+    /// of attachments are filled in as they are. The rich text is escaped when it is rendered by
+    /// a service. See [crate::template] for the syntax.
+    pub fn render(&self, ctx: &Context, markup: Markup) -> Result<Message<'static>, crate::Error> {
+        let mut msg = self.clone().into_owned();
+        template::render_opt(&mut msg.text, ctx, markup)?;
+        if let Some(doc) = &mut msg.rich_text {
+            doc.fill(ctx)?;
+        }
+        for hint in &mut msg.hints {
+            match hint {
+                Hint::Link(link) | Hint::Image(link) => {
+                    template::render_field(link, ctx, Markup::Plain)?
+                }
+                Hint::Description(des) | Hint::Title(des) => {
+                    template::render_field(des, ctx, markup)?
+                }
+                Hint::Author(text) | Hint::Footer(text) => {
+                    template::render_field(text, ctx, Markup::Plain)?
+                }
+                Hint::Field { name, value, .. } => {
+                    template::render_field(name, ctx, markup)?;
+                    template::render_field(value, ctx, markup)?;
+                }
+                Hint::Color(_)
+                | Hint::Timestamp(_)
+                | Hint::Urgency(_)
+                | Hint::DedupKey(_)
+                | Hint::Severity(_)
+                | Hint::Tag(_) => {}
+            }
+        }
+        for attachment in &mut msg.attachments {
+            attachment.fill(ctx)?;
+        }
+
+        Ok(msg)
+    }
 }
 
+/// The length of the text of a message in [Message::combine]; the limit of fields of Discord
+pub const MAX_COMBINED_FIELD: usize = 1024;
+
 /// They modify a Message or contain a specify information for a service
 /// (which other may ignore).
-#[derive(Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
 pub enum Hint<'a> {
     /// A Link
-    Link(&'a str),
+    Link(Cow<'a, str>),
     /// A Description
-    Description(&'a str),
+    Description(Cow<'a, str>),
+    /// A title shown above the message
+    Title(Cow<'a, str>),
+    /// A color as `0xRRGGBB`
+    Color(u32),
+    /// The url of an image
+    Image(Cow<'a, str>),
+    /// The name of the author
+    Author(Cow<'a, str>),
+    /// The time the message refers to
+    Timestamp(chrono::DateTime<chrono::Utc>),
+    /// A small text shown below the message
+    Footer(Cow<'a, str>),
+    /// A named value; services show fields like a table
+    Field {
+        /// The name of the field
+        name: Cow<'a, str>,
+        /// The value of the field
+        value: Cow<'a, str>,
+        /// Whether the field may be shown next to other fields
+        #[serde(default)]
+        inline: bool,
+    },
+    /// How important the message is
+    Urgency(Urgency),
+    /// Identifies repeats of a message for [throttling](crate::throttle) instead of its content;
+    /// services don't show it
+    DedupKey(Cow<'a, str>),
+    /// How severe the event of the message is, used for [routing](crate::routing); services
+    /// show it as [Urgency] if the message has no urgency
+    Severity(Severity),
+    /// A label of the message, used for [routing](crate::routing); services don't show it
+    Tag(Cow<'a, str>),
+}
+
+/// The urgency of a message
+///
+/// Services without urgency show it as the color of the message, unless there is a
+/// [Hint::Color]. Urgencies are ordered from low to critical.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Urgency {
+    /// Not important
+    Low,
+    /// The default
+    Normal,
+    /// Needs attention right now
+    Critical,
+}
+
+impl Urgency {
+    /// Returns the color as `0xRRGGBB` used for messages of this urgency
+    pub fn color(self) -> u32 {
+        match self {
+            Urgency::Low => 0x95a5a6,
+            Urgency::Normal => 0x3498db,
+            Urgency::Critical => 0xe74c3c,
+        }
+    }
+}
+
+/// The severity of the event of a message
+///
+/// Severities are ordered from debug to critical. Messages without a [Hint::Severity] have the
+/// severity [Severity::Info].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// Details for debugging
+    Debug,
+    /// Something happened as expected
+    #[default]
+    Info,
+    /// Something might need attention
+    Warning,
+    /// Something failed
+    Error,
+    /// Something failed and needs attention right now
+    Critical,
+}
+
+impl Severity {
+    /// Returns the urgency services show for messages of this severity
+    pub fn urgency(self) -> Urgency {
+        match self {
+            Severity::Debug => Urgency::Low,
+            Severity::Info | Severity::Warning => Urgency::Normal,
+            Severity::Error | Severity::Critical => Urgency::Critical,
+        }
+    }
+}
+
+impl<'a> Hint<'a> {
+    /// Converts the hint into one that doesn't borrow any data
+    pub fn into_owned(self) -> Hint<'static> {
+        match self {
+            Hint::Link(link) => Hint::Link(owned(link)),
+            Hint::Description(des) => Hint::Description(owned(des)),
+            Hint::Title(title) => Hint::Title(owned(title)),
+            Hint::Color(color) => Hint::Color(color),
+            Hint::Image(url) => Hint::Image(owned(url)),
+            Hint::Author(author) => Hint::Author(owned(author)),
+            Hint::Timestamp(time) => Hint::Timestamp(time),
+            Hint::Footer(footer) => Hint::Footer(owned(footer)),
+            Hint::Field {
+                name,
+                value,
+                inline,
+            } => Hint::Field {
+                name: owned(name),
+                value: owned(value),
+                inline,
+            },
+            Hint::Urgency(urgency) => Hint::Urgency(urgency),
+            Hint::DedupKey(key) => Hint::DedupKey(owned(key)),
+            Hint::Severity(severity) => Hint::Severity(severity),
+            Hint::Tag(tag) => Hint::Tag(owned(tag)),
+        }
+    }
+}
+
+/// Returns the heading of combined messages, e.g. `3 messages`
+pub(crate) fn count(messages: usize) -> String {
+    match messages {
+        1 => String::from("1 message"),
+        n => format!("{} messages", n),
+    }
+}
+
+/// Converts a possibly borrowed string into an owned one
+pub(crate) fn owned(s: Cow<'_, str>) -> Cow<'static, str> {
+    Cow::Owned(s.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hint, Message, Severity, Urgency};
+
+    #[tokio::test]
+    async fn test_into_owned() {
+        let text = String::from("some text");
+        let link = String::from("https://example.com/");
+        let msg = Message {
+            text: Some(text.as_str().into()),
+            rich_text: None,
+            hints: vec![Hint::Link(link.as_str().into())],
+            attachments: vec![],
+        };
+        let msg = msg.into_owned();
+        drop(text);
+        drop(link);
+
+        // an owned message can be moved into another task
+        let handle = tokio::spawn(async move { msg });
+        let msg = handle.await.unwrap();
+        assert_eq!(Some("some text"), msg.text.as_deref());
+        assert!(matches!(&msg.hints[0], Hint::Link(link) if link == "https://example.com/"));
+    }
+
+    #[test]
+    fn test_deserialize_schema() {
+        let json = r#"{
+            "text": "Deployment finished",
+            "hints": [
+                { "link": "https://example.com/deployments/42" },
+                { "description": "All services are up" },
+                { "title": "Deployment" },
+                { "color": 3066993 },
+                { "timestamp": "2022-12-24T18:00:00Z" },
+                { "field": { "name": "Version", "value": "1.2.0", "inline": true } },
+                { "urgency": "low" }
+            ],
+            "attachments": ["./deploy.log", "./screenshot.png"]
+        }"#;
+        let msg: Message = serde_json::from_str(json).unwrap();
+        assert_eq!(Some("Deployment finished"), msg.text.as_deref());
+        assert!(
+            matches!(&msg.hints[0], Hint::Link(l) if l == "https://example.com/deployments/42")
+        );
+        assert!(matches!(&msg.hints[1], Hint::Description(d) if d == "All services are up"));
+        assert!(matches!(&msg.hints[2], Hint::Title(t) if t == "Deployment"));
+        assert!(matches!(&msg.hints[3], Hint::Color(0x2ecc71)));
+        assert!(matches!(&msg.hints[4], Hint::Timestamp(t) if t.timestamp() == 1671904800));
+        assert!(matches!(
+            &msg.hints[5],
+            Hint::Field { name, value, inline: true } if name == "Version" && value == "1.2.0"
+        ));
+        assert!(matches!(&msg.hints[6], Hint::Urgency(Urgency::Low)));
+        assert_eq!("deploy.log", msg.attachments[0].file_name());
+        assert_eq!(Some("image/png"), msg.attachments[1].mime_type().as_deref());
+
+        let msg: Message = serde_json::from_str("{}").unwrap();
+        assert!(msg.text.is_none());
+        assert!(msg.hints.is_empty());
+    }
+
+    #[test]
+    fn test_combine() {
+        let mut first = Message::new("disk full");
+        first.hints.push(Hint::Title("db01".into()));
+        first.hints.push(Hint::Urgency(Urgency::Critical));
+        let mut second = Message::new("x".repeat(2000));
+        second.hints.push(Hint::Urgency(Urgency::Low));
+        second.attachments.push("./df.log".into());
+
+        let combined = Message::combine(&[first, second]);
+        assert_eq!(Some("2 messages"), combined.text.as_deref());
+        assert!(matches!(
+            &combined.hints[0],
+            Hint::Field { name, value, .. } if name == "db01" && value == "disk full"
+        ));
+        assert!(matches!(
+            &combined.hints[1],
+            Hint::Field { name, value, .. } if name == "#2" && value.chars().count() == 1024
+        ));
+        assert!(matches!(
+            &combined.hints[2],
+            Hint::Urgency(Urgency::Critical)
+        ));
+        assert_eq!("df.log", combined.attachments[0].file_name());
+    }
+
+    #[test]
+    fn test_severity_and_tags() {
+        let json = r#"{"text":"disk full","hints":[{"severity":"critical"},{"tag":"db"}]}"#;
+        let msg: Message = serde_json::from_str(json).unwrap();
+        assert_eq!(Severity::Critical, msg.severity());
+        assert_eq!(vec!["db"], msg.tags().collect::<Vec<_>>());
+        assert_eq!(json, serde_json::to_string(&msg).unwrap());
+
+        assert_eq!(Severity::Info, Message::new("text").severity());
+        assert!(Severity::Warning < Severity::Error);
+        assert_eq!(Urgency::Critical, Severity::Error.urgency());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut msg = Message::new("text");
+        msg.hints.push(Hint::Description("description".into()));
+
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            r#"{"text":"text","hints":[{"description":"description"}]}"#,
+            json
+        );
+        let back: Message = serde_json::from_str(&json).unwrap();
+        assert_eq!(json, serde_json::to_string(&back).unwrap());
+    }
 }
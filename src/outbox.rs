@@ -0,0 +1,587 @@
+//! A durable queue of messages that are delivered in the background.
+//!
+//! Messages that are put into the outbox are stored in a directory before they are sent, so they
+//! survive a restart of the process and a service that is down for a while. A [Worker] delivers
+//! them and sends them again until every target accepted them, which means that a target can
+//! get a message more than once, but doesn't miss it. Of a message that is sent in parts, e.g. a
+//! [split](crate::limits) text, only the parts that a target didn't receive are sent again:
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use announce_lib::outbox::{Outbox, Worker};
+//!
+//! # async fn example() -> Result<(), announce_lib::Error> {
+//! let outbox = Outbox::open("/var/spool/announce").await?;
+//! let announce = Arc::new(announce_lib::Announce::builder().outbox(outbox).build()?);
+//! let worker = Worker::spawn(announce.clone())?;
+//!
+//! let urls = vec![reqwest::Url::parse("discord://WEBHOOK_ID/WEBHOOK_TOKEN")?];
+//! announce
+//!     .enqueue(urls, &announce_lib::Message::new("Backup finished"))
+//!     .await?;
+//!
+//! // delivers what is still pending before the process exits
+//! let pending = worker.shutdown().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Every message is a JSON file named after its id. The files contain the urls of the targets
+//! with their secrets, so the directory should only be readable by the process; on unix the
+//! outbox creates its directories and files that way. Messages that could not be delivered
+//! within the attempts of the [RetryPolicy] are moved to the subdirectory `failed`. A directory
+//! should only be used by one process at a time.
+//!
+//! Only attachments from files can be put into the outbox; the files are read when the message
+//! is delivered.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::attachment::Source;
+use crate::{Announce, Error, Message, RetryPolicy};
+
+/// The subdirectory of messages that could not be delivered
+const FAILED_DIR: &str = "failed";
+
+/// How long the [Worker] waits if there is nothing to deliver and no message is added
+const IDLE_WAIT: Duration = Duration::from_secs(60);
+
+/// The number of days a message waits for its next attempt at most
+const MAX_NEXT_ATTEMPT_DAYS: i64 = 365;
+
+/// A queue of messages in a directory, see [the module](self)
+pub struct Outbox {
+    dir: PathBuf,
+    retry: RetryPolicy,
+    /// wakes the worker when a message is added
+    added: tokio::sync::Notify,
+    /// makes sure that a message is not delivered by two tasks at the same time
+    delivering: tokio::sync::Mutex<()>,
+}
+
+/// A message in the outbox
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// The targets that didn't accept the message yet
+    targets: Vec<reqwest::Url>,
+    /// The number of parts of a message that is sent in parts that a target already received,
+    /// by the url of the target
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    sent_parts: HashMap<String, usize>,
+    message: Message<'static>,
+    created_at: DateTime<Utc>,
+    /// How often delivering the message failed
+    attempts: u32,
+    next_attempt: DateTime<Utc>,
+    /// The errors of the last attempt, without secrets
+    last_error: Option<String>,
+}
+
+impl Outbox {
+    /// Opens the outbox in `dir` and creates the directory if it doesn't exist.
+    ///
+    /// On unix new directories are only accessible by the user of the process; the permissions
+    /// of an existing directory are kept.
+    pub async fn open(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        let mut builder = tokio::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        builder.mode(0o700);
+        builder.create(dir.join(FAILED_DIR)).await?;
+
+        Ok(Self {
+            dir,
+            retry: Self::default_retry_policy(),
+            added: tokio::sync::Notify::new(),
+            delivering: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// The policy of an outbox, unless it is set with [Outbox::with_retry_policy].
+    ///
+    /// Messages are sent at most 20 times, with a backoff from 5 seconds up to an hour.
+    pub fn default_retry_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 20,
+            initial_backoff: Duration::from_secs(5),
+            max_backoff: Duration::from_secs(60 * 60),
+            multiplier: 2.0,
+            jitter: true,
+        }
+    }
+
+    /// Sets how often and when a message is sent again after a target didn't accept it.
+    ///
+    /// This is independent of the [RetryPolicy] of [Announce], which retries single requests.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Returns the directory of the outbox
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Stores a message for the given targets and returns its id.
+    ///
+    /// Use [Announce::enqueue], which checks the targets, instead of calling this directly.
+    pub async fn push(&self, urls: Vec<reqwest::Url>, msg: &Message<'_>) -> Result<String, Error> {
+        if msg
+            .attachments
+            .iter()
+            .any(|file| !matches!(file.source, Source::Path(_)))
+        {
+            return Err(Error::Generic(String::from(
+                "only attachments from files can be put into the outbox",
+            )));
+        }
+
+        let now = Utc::now();
+        let id = format!(
+            "{:013}-{:08x}",
+            now.timestamp_millis(),
+            rand::thread_rng().gen::<u32>()
+        );
+        let entry = Entry {
+            targets: urls,
+            sent_parts: HashMap::new(),
+            message: msg.clone().into_owned(),
+            created_at: now,
+            attempts: 0,
+            next_attempt: now,
+            last_error: None,
+        };
+        self.save(&id, &entry).await?;
+        self.added.notify_one();
+
+        Ok(id)
+    }
+
+    /// Returns the number of messages that are waiting to be delivered
+    pub async fn depth(&self) -> Result<usize, Error> {
+        Ok(self.ids(&self.dir).await?.len())
+    }
+
+    /// Returns the number of messages that were given up, see [the module](self)
+    pub async fn failed(&self) -> Result<usize, Error> {
+        Ok(self.ids(&self.dir.join(FAILED_DIR)).await?.len())
+    }
+
+    /// Tries to deliver every message now, even if its next attempt is due later.
+    ///
+    /// Returns the number of messages that are still waiting afterwards.
+    pub async fn flush(&self, announce: &Announce) -> Result<usize, Error> {
+        self.deliver(announce, false).await?;
+        self.depth().await
+    }
+
+    /// Delivers the messages whose next attempt is due.
+    ///
+    /// Returns when the next of the remaining messages is due, if there is one.
+    pub async fn deliver_due(&self, announce: &Announce) -> Result<Option<DateTime<Utc>>, Error> {
+        self.deliver(announce, true).await
+    }
+
+    async fn deliver(
+        &self,
+        announce: &Announce,
+        due_only: bool,
+    ) -> Result<Option<DateTime<Utc>>, Error> {
+        let _delivering = self.delivering.lock().await;
+
+        let mut next: Option<DateTime<Utc>> = None;
+        for id in self.ids(&self.dir).await? {
+            let mut entry = match self.load(&id).await {
+                Ok(entry) => entry,
+                Err(e) => {
+                    log::warn!("skipping message {} of the outbox: {}", id, e);
+                    continue;
+                }
+            };
+            if due_only && entry.next_attempt > Utc::now() {
+                next = Some(next.map_or(entry.next_attempt, |next| next.min(entry.next_attempt)));
+                continue;
+            }
+
+            // parts of a message that a target received are not sent again
+            let targets = entry
+                .targets
+                .iter()
+                .map(|url| {
+                    let sent = entry.sent_parts.get(url.as_str()).copied();
+                    (url.clone(), sent.unwrap_or(0))
+                })
+                .collect();
+            let results = announce.announce_parts(targets, &entry.message).await;
+            let mut errors = vec![];
+            let mut pending = vec![];
+            let mut sent_parts = HashMap::new();
+            for (url, result) in entry.targets.into_iter().zip(results) {
+                match result {
+                    Ok(receipt) if receipt.success => continue,
+                    // a summary of the throttle tells about the repeat
+                    Err(crate::Error::Suppressed { .. }) => continue,
+                    Ok(receipt) => errors.push(format!(
+                        "{}: not accepted (status: {:?})",
+                        receipt.target, receipt.status
+                    )),
+                    Err(e) => {
+                        if let crate::Error::PartiallySent { sent, .. } = e {
+                            sent_parts.insert(url.to_string(), sent);
+                        } else if let Some(sent) = entry.sent_parts.remove(url.as_str()) {
+                            sent_parts.insert(url.to_string(), sent);
+                        }
+                        errors.push(format!("{}: {}", announce.redact(&url), e));
+                    }
+                }
+                pending.push(url);
+            }
+
+            let path = self.path(&id);
+            if pending.is_empty() {
+                log::debug!("delivered message {} of the outbox", id);
+                tokio::fs::remove_file(&path).await?;
+                continue;
+            }
+
+            entry.targets = pending;
+            entry.sent_parts = sent_parts;
+            entry.attempts += 1;
+            entry.last_error = Some(errors.join("; "));
+            if entry.attempts >= self.retry.max_attempts.max(1) {
+                log::warn!(
+                    "giving up message {} of the outbox after {} attempts: {}",
+                    id,
+                    entry.attempts,
+                    errors.join("; ")
+                );
+                self.save(&id, &entry).await?;
+                let failed = self.dir.join(FAILED_DIR).join(format!("{}.json", id));
+                tokio::fs::rename(&path, failed).await?;
+                continue;
+            }
+
+            let backoff = self.retry.backoff(entry.attempts);
+            entry.next_attempt = next_attempt(backoff);
+            log::debug!(
+                "delivering message {} of the outbox failed, retrying in {:?}: {}",
+                id,
+                backoff,
+                errors.join("; ")
+            );
+            self.save(&id, &entry).await?;
+            next = Some(next.map_or(entry.next_attempt, |next| next.min(entry.next_attempt)));
+        }
+
+        Ok(next)
+    }
+
+    /// Returns the ids of the messages in a directory, the oldest first
+    async fn ids(&self, dir: &Path) -> Result<Vec<String>, Error> {
+        let mut ids = vec![];
+        let mut entries = tokio::fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            if let Some(id) = name.to_str().and_then(|name| name.strip_suffix(".json")) {
+                ids.push(String::from(id));
+            }
+        }
+        ids.sort();
+
+        Ok(ids)
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    async fn load(&self, id: &str) -> Result<Entry, Error> {
+        let content = tokio::fs::read(self.path(id)).await?;
+        Ok(serde_json::from_slice(&content)?)
+    }
+
+    /// Writes an entry to a temporary file first, so that a crash never leaves half of it
+    ///
+    /// On unix the file is only readable by the user of the process, it contains secrets.
+    async fn save(&self, id: &str, entry: &Entry) -> Result<(), Error> {
+        let tmp = self.dir.join(format!("{}.tmp", id));
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(&tmp).await?;
+        file.write_all(&serde_json::to_vec(entry)?).await?;
+        file.flush().await?;
+        drop(file);
+        tokio::fs::rename(&tmp, self.path(id)).await?;
+        Ok(())
+    }
+}
+
+/// Returns when a message is sent again after `backoff`
+///
+/// A backoff that doesn't fit into a date, e.g. of a huge [RetryPolicy::max_backoff], is cut to
+/// [MAX_NEXT_ATTEMPT_DAYS].
+fn next_attempt(backoff: Duration) -> DateTime<Utc> {
+    let now = Utc::now();
+    chrono::Duration::from_std(backoff)
+        .ok()
+        .filter(|backoff| *backoff <= chrono::Duration::days(MAX_NEXT_ATTEMPT_DAYS))
+        .and_then(|backoff| now.checked_add_signed(backoff))
+        .unwrap_or_else(|| now + chrono::Duration::days(MAX_NEXT_ATTEMPT_DAYS))
+}
+
+/// A task that delivers the messages of the [Outbox] of an [Announce] object in the background
+pub struct Worker {
+    announce: Arc<Announce>,
+    stop: tokio::sync::oneshot::Sender<()>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Worker {
+    /// Starts delivering the messages of the outbox of `announce`.
+    ///
+    /// Returns [Error::Config] if `announce` has no outbox.
+    pub fn spawn(announce: Arc<Announce>) -> Result<Self, Error> {
+        if announce.outbox().is_none() {
+            return Err(Error::Config(String::from("no outbox is configured")));
+        }
+
+        let (stop, mut stopped) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn({
+            let announce = announce.clone();
+            async move {
+                let outbox = match announce.outbox() {
+                    Some(outbox) => outbox,
+                    None => return,
+                };
+                loop {
+                    let wait = match outbox.deliver_due(&announce).await {
+                        Ok(Some(next)) => (next - Utc::now()).to_std().unwrap_or(Duration::ZERO),
+                        Ok(None) => IDLE_WAIT,
+                        Err(e) => {
+                            log::warn!("delivering the outbox failed: {}", e);
+                            outbox.retry.initial_backoff
+                        }
+                    };
+                    tokio::select! {
+                        _ = &mut stopped => break,
+                        _ = outbox.added.notified() => {}
+                        _ = tokio::time::sleep(wait) => {}
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            announce,
+            stop,
+            task,
+        })
+    }
+
+    /// Stops the worker and tries to deliver every message that is still waiting once more.
+    ///
+    /// Returns the number of messages that are left in the outbox.
+    pub async fn shutdown(self) -> Result<usize, Error> {
+        let _ = self.stop.send(());
+        let _ = self.task.await;
+        match self.announce.outbox() {
+            Some(outbox) => outbox.flush(&self.announce).await,
+            None => Ok(0),
+        }
+    }
+}
+
+// the tests send to Discord through a mock transport
+#[cfg(all(test, feature = "discord"))]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{Outbox, Worker};
+    use crate::transport::{MockResponse, MockTransport};
+    use crate::{Announce, Message, RetryPolicy};
+
+    /// Returns an empty directory for a test
+    fn dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("announce-outbox-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn urls() -> Vec<reqwest::Url> {
+        vec![reqwest::Url::parse("discord://1234/token").unwrap()]
+    }
+
+    async fn announce(dir: &std::path::Path, transport: Arc<MockTransport>) -> Announce {
+        let outbox = Outbox::open(dir)
+            .await
+            .unwrap()
+            .with_retry_policy(RetryPolicy {
+                max_attempts: 2,
+                initial_backoff: Duration::from_secs(60),
+                jitter: false,
+                ..Default::default()
+            });
+        Announce::builder()
+            .transport(transport)
+            .retry_policy(RetryPolicy::none())
+            .outbox(outbox)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_messages_survive_restarts() {
+        let dir = dir("restart");
+        let transport = Arc::new(MockTransport::new());
+        let first = announce(&dir, transport.clone()).await;
+        first.enqueue(urls(), &Message::new("text")).await.unwrap();
+        assert_eq!(1, first.outbox().unwrap().depth().await.unwrap());
+        assert!(transport.requests().is_empty());
+        drop(first);
+
+        let second = announce(&dir, transport.clone()).await;
+        let outbox = second.outbox().unwrap();
+        assert_eq!(1, outbox.depth().await.unwrap());
+        assert_eq!(0, outbox.flush(&second).await.unwrap());
+        assert_eq!("text", transport.requests()[0].json().unwrap()["content"]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_failed_messages_are_retried() {
+        let dir = dir("retry");
+        let transport = Arc::new(MockTransport::new());
+        let announce = announce(&dir, transport.clone()).await;
+        let outbox = announce.outbox().unwrap();
+        announce
+            .enqueue(urls(), &Message::new("text"))
+            .await
+            .unwrap();
+
+        transport.push_response(MockResponse::new(500));
+        let next = outbox.deliver_due(&announce).await.unwrap().unwrap();
+        assert!(next > chrono::Utc::now());
+        assert_eq!(1, outbox.depth().await.unwrap());
+
+        // the next attempt is not due yet
+        outbox.deliver_due(&announce).await.unwrap();
+        assert_eq!(1, transport.requests().len());
+
+        // the second attempt is the last one of the policy
+        transport.push_response(MockResponse::new(500));
+        assert_eq!(0, outbox.flush(&announce).await.unwrap());
+        assert_eq!(1, outbox.failed().await.unwrap());
+
+        let bytes = crate::Attachment::bytes(vec![1, 2, 3], "data.bin");
+        let mut msg = Message::new("text");
+        msg.attachments.push(bytes);
+        assert!(announce.enqueue(urls(), &msg).await.is_err());
+        let unknown = vec![reqwest::Url::parse("unknown://host").unwrap()];
+        assert!(matches!(
+            announce.enqueue(unknown, &Message::new("text")).await,
+            Err(crate::Error::NoMatchingSchema)
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_sent_parts_are_skipped() {
+        let dir = dir("parts");
+        let transport = Arc::new(MockTransport::new());
+        let mut announce = announce(&dir, transport.clone()).await;
+        announce.set_split_long_messages(true);
+        let outbox = announce.outbox().unwrap();
+        announce
+            .enqueue(urls(), &Message::new("word ".repeat(600)))
+            .await
+            .unwrap();
+
+        // the first part is delivered, the second one fails
+        transport.push_response(MockResponse::new(204));
+        transport.push_response(MockResponse::new(500));
+        assert_eq!(1, outbox.flush(&announce).await.unwrap());
+        assert_eq!(2, transport.requests().len());
+
+        // only the second part is sent again
+        assert_eq!(0, outbox.flush(&announce).await.unwrap());
+        let requests = transport.requests();
+        assert_eq!(3, requests.len());
+        assert_eq!(requests[1].json().unwrap(), requests[2].json().unwrap());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_worker() {
+        let dir = dir("worker");
+        let transport = Arc::new(MockTransport::new());
+        let announce = Arc::new(announce(&dir, transport.clone()).await);
+        let worker = Worker::spawn(announce.clone()).unwrap();
+        announce
+            .enqueue(urls(), &Message::new("text"))
+            .await
+            .unwrap();
+
+        for _ in 0..100 {
+            if !transport.requests().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(1, transport.requests().len());
+        assert_eq!(0, worker.shutdown().await.unwrap());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = dir("permissions");
+        let transport = Arc::new(MockTransport::new());
+        let announce = announce(&dir, transport).await;
+        let id = announce
+            .enqueue(urls(), &Message::new("text"))
+            .await
+            .unwrap();
+
+        let mode =
+            |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(0o700, mode(&dir));
+        assert_eq!(0o700, mode(&dir.join(super::FAILED_DIR)));
+        assert_eq!(0o600, mode(&dir.join(format!("{}.json", id))));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_next_attempt() {
+        let now = chrono::Utc::now();
+        let next = super::next_attempt(Duration::from_secs(60));
+        assert!(next > now && next <= chrono::Utc::now() + chrono::Duration::seconds(60));
+
+        // a backoff that doesn't fit into a date is cut
+        let max = chrono::Duration::days(super::MAX_NEXT_ATTEMPT_DAYS);
+        let next = super::next_attempt(Duration::MAX);
+        assert!(next > now + max - chrono::Duration::seconds(1));
+        assert!(next <= chrono::Utc::now() + max);
+    }
+}
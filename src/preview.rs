@@ -0,0 +1,216 @@
+//! Descriptions of what would be sent to services, without sending anything.
+//!
+//! A dry run builds the same requests as sending a message, but returns a [Preview] of every
+//! request instead of executing it. Previews can be serialized, e.g. to review changes of
+//! notifications or to compare them in tests:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), announce_lib::Error> {
+//! let announce = announce_lib::Announce::new().await?;
+//! let urls = vec![reqwest::Url::parse("discord://WEBHOOK_ID/WEBHOOK_TOKEN")?];
+//! let msg = announce_lib::Message::new("Deployment finished");
+//!
+//! for previews in announce.dry_run(urls, &msg).await {
+//!     println!("{}", serde_json::to_string_pretty(&previews?)?);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Secrets like tokens are replaced with `***`, see [crate::redact]. Files are not read for a
+//! preview, so attachments from readers can still be sent afterwards.
+//!
+//! Own services can support dry runs by implementing
+//! [DynService::preview](crate::service::DynService::preview) with the types of this module.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::attachment::Attachment;
+
+/// A request that would be sent to a service
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Preview {
+    /// A request to a HTTP Api
+    Http(HttpRequest),
+
+    /// A call of a D-Bus method
+    Dbus(DbusCall),
+}
+
+/// A HTTP request that would be sent
+#[derive(Debug, Clone, Serialize)]
+pub struct HttpRequest {
+    /// The method of the request, e.g. `POST`
+    pub method: String,
+
+    /// The url of the request without secrets
+    pub url: String,
+
+    /// The headers of the request without secrets
+    pub headers: BTreeMap<String, String>,
+
+    /// The body of the request
+    pub body: Body,
+}
+
+/// The body of a [HttpRequest]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", content = "content", rename_all = "snake_case")]
+pub enum Body {
+    /// A request without body
+    Empty,
+
+    /// A JSON document
+    Json(serde_json::Value),
+
+    /// A body that is not JSON
+    Text(String),
+
+    /// A multipart form, e.g. for uploading files
+    Multipart(Vec<Part>),
+}
+
+/// A part of a multipart form
+#[derive(Debug, Clone, Serialize)]
+pub struct Part {
+    /// The name of the part
+    pub name: String,
+
+    /// The content of a part that is not a file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+
+    /// The name of a file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+
+    /// The MIME type of the part
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime: Option<String>,
+}
+
+/// A call of a D-Bus method that would be made
+#[derive(Debug, Clone, Serialize)]
+pub struct DbusCall {
+    /// The bus name of the receiver
+    pub destination: String,
+
+    /// The object path of the receiver
+    pub path: String,
+
+    /// The interface of the method
+    pub interface: String,
+
+    /// The name of the method
+    pub member: String,
+
+    /// The arguments of the call by their names
+    pub args: serde_json::Map<String, serde_json::Value>,
+}
+
+impl HttpRequest {
+    /// Describes a request; the secrets of the url and the headers are replaced with `***` as
+    /// described in [crate::redact]
+    pub fn new(req: &reqwest::Request) -> Self {
+        let headers = req
+            .headers()
+            .iter()
+            .map(|(name, value)| (name.to_string(), crate::redact::header(name, value)))
+            .collect();
+        let is_json = req
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.contains("json"));
+        let body = match req.body().and_then(reqwest::Body::as_bytes) {
+            None => Body::Empty,
+            Some(bytes) if is_json => match serde_json::from_slice(bytes) {
+                Ok(json) => Body::Json(json),
+                Err(_) => Body::Text(String::from_utf8_lossy(bytes).into_owned()),
+            },
+            Some(bytes) => Body::Text(String::from_utf8_lossy(bytes).into_owned()),
+        };
+
+        Self {
+            method: req.method().to_string(),
+            url: crate::redact::url(req.url()),
+            headers,
+            body,
+        }
+    }
+
+    /// Sets a multipart form as body
+    pub fn with_parts(mut self, parts: Vec<Part>) -> Self {
+        self.headers.insert(
+            String::from("content-type"),
+            String::from("multipart/form-data"),
+        );
+        self.body = Body::Multipart(parts);
+        self
+    }
+}
+
+impl Part {
+    /// A part with text
+    pub fn text(name: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            text: Some(text.into()),
+            filename: None,
+            mime: None,
+        }
+    }
+
+    /// A part with a file; the file is not read
+    pub fn file(name: impl Into<String>, file: &Attachment<'_>) -> Self {
+        Self {
+            name: name.into(),
+            text: None,
+            filename: Some(file.file_name().into_owned()),
+            mime: file.mime_type().map(|mime| mime.into_owned()),
+        }
+    }
+
+    /// Sets the MIME type of the part
+    pub fn with_mime(mut self, mime: impl Into<String>) -> Self {
+        self.mime = Some(mime.into());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Body, HttpRequest, Part};
+
+    #[test]
+    fn test_http_request() {
+        let client = reqwest::Client::new();
+        let req = client
+            .post("https://example.com/hooks?token=secret")
+            .header("x-auth-token", "secret")
+            .header("x-user-id", "user")
+            .json(&serde_json::json!({ "text": "hello" }))
+            .build()
+            .unwrap();
+        let preview = HttpRequest::new(&req);
+        assert_eq!("POST", preview.method);
+        assert_eq!("https://example.com/hooks?token=***", preview.url);
+        assert_eq!("***", preview.headers["x-auth-token"]);
+        assert_eq!("user", preview.headers["x-user-id"]);
+        assert!(matches!(&preview.body, Body::Json(json) if json["text"] == "hello"));
+
+        let file = crate::Attachment::bytes(vec![1, 2, 3], "data.png");
+        let preview =
+            preview.with_parts(vec![Part::text("msg", "hello"), Part::file("file", &file)]);
+        assert_eq!(
+            serde_json::json!([
+                { "name": "msg", "text": "hello" },
+                { "name": "file", "filename": "data.png", "mime": "image/png" }
+            ]),
+            serde_json::to_value(&preview).unwrap()["body"]["content"]
+        );
+    }
+}
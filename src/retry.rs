@@ -0,0 +1,346 @@
+//! Retrying of requests to HTTP services that failed for transient reasons.
+
+use std::future::Future;
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::transport::Transport;
+
+/// Decides how often and how long to wait before a failed request is sent again.
+///
+/// A request is retried when the service responds with `429 Too Many Requests`,
+/// `502 Bad Gateway`, `503 Service Unavailable` or `504 Gateway Timeout` or when a connection
+/// could not be established in time. Other server errors are not retried: the service may have
+/// accepted the message anyway and it would arrive twice.
+/// Timeouts while waiting for a response are not retried either, for the same reason.
+/// If the service tells how long to wait (e.g. with a `Retry-After` header) that time is used
+/// instead of the computed backoff. If it asks for longer than [RetryPolicy::max_backoff], the
+/// request is not retried and [Error::RateLimited](crate::Error::RateLimited) is returned.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// How often a request is sent at most, including the first attempt
+    pub max_attempts: u32,
+
+    /// The time to wait before the first retry
+    pub initial_backoff: Duration,
+
+    /// The time to wait between retries does not grow beyond this
+    pub max_backoff: Duration,
+
+    /// Factor by which the backoff grows after every retry
+    pub multiplier: f64,
+
+    /// Randomizes the backoff between half and the full value, so that many clients don't
+    /// retry at the same time
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that sends every request exactly once
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the time to wait after the given failed attempt (starting with 1)
+    ///
+    /// A negative backoff or one that is not a number, e.g. of a negative
+    /// [RetryPolicy::multiplier], is no wait; an infinite one is [RetryPolicy::max_backoff].
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        let backoff = match backoff.is_nan() {
+            true => 0.0,
+            false => backoff.clamp(0.0, self.max_backoff.as_secs_f64()),
+        };
+        let backoff = if self.jitter && backoff > 0.0 {
+            rand::thread_rng().gen_range(backoff / 2.0..=backoff)
+        } else {
+            backoff
+        };
+
+        Duration::try_from_secs_f64(backoff).unwrap_or(self.max_backoff)
+    }
+}
+
+/// Returns true if a response with this status is worth retrying
+///
+/// These statuses mean that the request didn't reach the service or wasn't processed.
+fn is_transient(status: reqwest::StatusCode) -> bool {
+    matches!(
+        status,
+        reqwest::StatusCode::TOO_MANY_REQUESTS
+            | reqwest::StatusCode::BAD_GATEWAY
+            | reqwest::StatusCode::SERVICE_UNAVAILABLE
+            | reqwest::StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Returns true if a request failed because no connection could be established in time
+fn is_connection_error(error: &crate::Error) -> bool {
+    match error {
+        crate::Error::Reqwest(e) => e.is_connect(),
+        crate::Error::Connection(_) => true,
+        _ => false,
+    }
+}
+
+/// Sends a request with `transport` and retries it according to `policy`.
+///
+/// `build` is called for every attempt, because request bodies can not always be reused.
+/// `retry_after` extracts the time a service wants the client to wait from a response.
+///
+/// If the last attempt was rate limited, or the service asks to wait longer than
+/// [RetryPolicy::max_backoff], [Error::RateLimited](crate::Error::RateLimited) is returned,
+/// otherwise [Error::RetriesExhausted](crate::Error::RetriesExhausted).
+pub async fn execute<B, F>(
+    transport: &dyn Transport,
+    policy: &RetryPolicy,
+    mut build: B,
+    retry_after: fn(&reqwest::header::HeaderMap) -> Option<Duration>,
+) -> Result<reqwest::Response, crate::Error>
+where
+    B: FnMut() -> F,
+    F: Future<Output = Result<reqwest::Request, crate::Error>>,
+{
+    let max_attempts = policy.max_attempts.max(1);
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let req = build().await?;
+        log::trace!(
+            "attempt {} of {}: {}",
+            attempt,
+            max_attempts,
+            crate::redact::request(&req)
+        );
+
+        let (status, wait, source) = match transport.execute(req).await {
+            Ok(response) if !is_transient(response.status()) => return Ok(response),
+            Ok(response) => {
+                let wait = retry_after(response.headers());
+                (Some(response.status()), wait, None)
+            }
+            Err(e) if is_connection_error(&e) => (None, None, Some(Box::new(e))),
+            Err(e) => return Err(e),
+        };
+
+        if attempt >= max_attempts {
+            if status == Some(reqwest::StatusCode::TOO_MANY_REQUESTS) {
+                return Err(crate::Error::RateLimited { retry_after: wait });
+            }
+            return Err(crate::Error::RetriesExhausted {
+                attempts: attempt,
+                status,
+                retry_after: wait,
+                source,
+            });
+        }
+
+        let wait = match wait {
+            Some(wait) if wait > policy.max_backoff => {
+                return Err(crate::Error::RateLimited {
+                    retry_after: Some(wait),
+                })
+            }
+            Some(wait) => wait,
+            None => policy.backoff(attempt),
+        };
+        log::debug!(
+            "request failed (status: {:?}), retrying in {:?}",
+            status,
+            wait
+        );
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Reads a header as a floating point number
+pub fn header_f64(headers: &reqwest::header::HeaderMap, name: &str) -> Option<f64> {
+    headers
+        .get(name)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|value| value.is_finite() && *value >= 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2.0,
+            jitter: false,
+        };
+        assert_eq!(Duration::from_secs(1), policy.backoff(1));
+        assert_eq!(Duration::from_secs(2), policy.backoff(2));
+        assert_eq!(Duration::from_secs(4), policy.backoff(3));
+        assert_eq!(Duration::from_secs(5), policy.backoff(4));
+        assert_eq!(Duration::from_secs(5), policy.backoff(100));
+    }
+
+    #[test]
+    fn test_backoff_jitter() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_secs(4),
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            let backoff = policy.backoff(1);
+            assert!(backoff >= Duration::from_secs(2));
+            assert!(backoff <= Duration::from_secs(4));
+        }
+    }
+
+    #[test]
+    fn test_backoff_is_never_negative() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_secs(1),
+            multiplier: -2.0,
+            ..Default::default()
+        };
+        for attempt in 1..10 {
+            assert!(policy.backoff(attempt) <= policy.max_backoff);
+        }
+        assert_eq!(Duration::ZERO, policy.backoff(2));
+
+        let policy = RetryPolicy {
+            multiplier: f64::NAN,
+            ..Default::default()
+        };
+        assert_eq!(Duration::ZERO, policy.backoff(2));
+
+        let policy = RetryPolicy {
+            multiplier: f64::INFINITY,
+            max_backoff: Duration::MAX,
+            jitter: false,
+            ..Default::default()
+        };
+        assert_eq!(Duration::MAX, policy.backoff(2));
+    }
+
+    #[test]
+    fn test_transient_statuses() {
+        assert!(super::is_transient(reqwest::StatusCode::TOO_MANY_REQUESTS));
+        assert!(super::is_transient(
+            reqwest::StatusCode::SERVICE_UNAVAILABLE
+        ));
+        assert!(!super::is_transient(
+            reqwest::StatusCode::INTERNAL_SERVER_ERROR
+        ));
+        assert!(!super::is_transient(reqwest::StatusCode::BAD_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn test_execute_retries_transient_failures() {
+        use crate::transport::{MockResponse, MockTransport};
+
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::ZERO,
+            jitter: false,
+            ..Default::default()
+        };
+        let client = reqwest::Client::new();
+        let build = || async { Ok(client.get("https://example.com/").build()?) };
+        let retry_after = |_: &reqwest::header::HeaderMap| None;
+
+        let transport = MockTransport::new();
+        transport.push_error(crate::Error::Connection(String::from("refused")));
+        transport.push_response(MockResponse::rate_limited(Duration::ZERO));
+        transport.push_response(MockResponse::new(204));
+        let response = super::execute(&transport, &policy, build, retry_after)
+            .await
+            .unwrap();
+        assert_eq!(204, response.status().as_u16());
+        assert_eq!(3, transport.requests().len());
+
+        let transport = MockTransport::new();
+        for _ in 0..3 {
+            transport.push_error(crate::Error::Connection(String::from("refused")));
+        }
+        assert!(matches!(
+            super::execute(&transport, &policy, build, retry_after).await,
+            Err(crate::Error::RetriesExhausted {
+                attempts: 3,
+                status: None,
+                source: Some(_),
+                ..
+            })
+        ));
+
+        let transport = MockTransport::new();
+        for _ in 0..3 {
+            transport.push_response(MockResponse::rate_limited(Duration::ZERO));
+        }
+        assert!(matches!(
+            super::execute(&transport, &policy, build, retry_after).await,
+            Err(crate::Error::RateLimited { .. })
+        ));
+
+        // other errors are not retried
+        let transport = MockTransport::new();
+        transport.push_error(crate::Error::Generic(String::from("broken")));
+        assert!(matches!(
+            super::execute(&transport, &policy, build, retry_after).await,
+            Err(crate::Error::Generic(_))
+        ));
+        assert_eq!(1, transport.requests().len());
+    }
+
+    #[tokio::test]
+    async fn test_execute_does_not_wait_beyond_max_backoff() {
+        use crate::transport::{MockResponse, MockTransport};
+
+        let policy = RetryPolicy {
+            max_backoff: Duration::from_secs(30),
+            ..Default::default()
+        };
+        let client = reqwest::Client::new();
+        let build = || async { Ok(client.get("https://example.com/").build()?) };
+        let retry_after = |headers: &reqwest::header::HeaderMap| {
+            super::header_f64(headers, "Retry-After").map(Duration::from_secs_f64)
+        };
+
+        let transport = MockTransport::new();
+        transport.push_response(MockResponse::rate_limited(Duration::from_secs(86400)));
+        transport.push_response(MockResponse::new(204));
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            super::execute(&transport, &policy, build, retry_after),
+        )
+        .await
+        .expect("a long Retry-After is not waited for");
+        assert!(matches!(
+            result,
+            Err(crate::Error::RateLimited {
+                retry_after: Some(wait)
+            }) if wait == Duration::from_secs(86400)
+        ));
+        assert_eq!(1, transport.requests().len());
+    }
+}
@@ -0,0 +1,448 @@
+//! A small rich text document that every service renders in its own markup.
+//!
+//! Services that support no markup get plain text that keeps the structure readable, e.g. links
+//! are written as `text (url)`.
+//!
+//! # Example
+//! ```
+//! use announce_lib::markup::Markup;
+//! use announce_lib::richtext::{Document, Inline};
+//!
+//! let doc = Document::new()
+//!     .paragraph(vec![Inline::bold("Deployment"), Inline::text(" finished")])
+//!     .list(vec![
+//!         vec![Inline::code("api"), Inline::text(" is up")],
+//!         vec![Inline::code("worker"), Inline::text(" is up")],
+//!     ]);
+//!
+//! assert_eq!(
+//!     "**Deployment** finished\n- `api` is up\n- `worker` is up",
+//!     doc.render(Markup::Discord)
+//! );
+//! assert_eq!(
+//!     "<b>Deployment</b> finished\n• api is up\n• worker is up",
+//!     doc.render(Markup::Dbus)
+//! );
+//! ```
+
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::markup::Markup;
+use crate::message::owned;
+use crate::template::{self, Context};
+
+/// A rich text document made of blocks
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(transparent)]
+pub struct Document<'a> {
+    /// The blocks of the document, one after another
+    pub blocks: Vec<Block<'a>>,
+}
+
+/// A part of a [Document] that starts on its own line
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Block<'a> {
+    /// A paragraph of text
+    Paragraph(Vec<Inline<'a>>),
+    /// Preformatted code
+    CodeBlock {
+        /// The language of the code, used for highlighting by some services
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        language: Option<Cow<'a, str>>,
+        /// The code itself
+        code: Cow<'a, str>,
+    },
+    /// A bullet list; every item is a line of text
+    List(Vec<Vec<Inline<'a>>>),
+    /// A quote of other blocks
+    Quote(Vec<Block<'a>>),
+}
+
+/// Formatted text inside of a [Block]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Inline<'a> {
+    /// Text without formatting
+    Text(Cow<'a, str>),
+    /// Bold text
+    Bold(Vec<Inline<'a>>),
+    /// Italic text
+    Italic(Vec<Inline<'a>>),
+    /// Inline code
+    Code(Cow<'a, str>),
+    /// A link with a text
+    Link {
+        /// The text shown for the link
+        text: Vec<Inline<'a>>,
+        /// The target of the link
+        url: Cow<'a, str>,
+    },
+    /// Mentions a user by name; on Discord a numeric id mentions the user with that id
+    Mention(Cow<'a, str>),
+}
+
+impl<'a> Document<'a> {
+    /// Creates an empty document
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a paragraph
+    pub fn paragraph(mut self, inlines: Vec<Inline<'a>>) -> Self {
+        self.blocks.push(Block::Paragraph(inlines));
+        self
+    }
+
+    /// Adds a block of code
+    pub fn code_block(
+        mut self,
+        language: Option<impl Into<Cow<'a, str>>>,
+        code: impl Into<Cow<'a, str>>,
+    ) -> Self {
+        self.blocks.push(Block::CodeBlock {
+            language: language.map(Into::into),
+            code: code.into(),
+        });
+        self
+    }
+
+    /// Adds a bullet list
+    pub fn list(mut self, items: Vec<Vec<Inline<'a>>>) -> Self {
+        self.blocks.push(Block::List(items));
+        self
+    }
+
+    /// Adds a quote
+    pub fn quote(mut self, blocks: Vec<Block<'a>>) -> Self {
+        self.blocks.push(Block::Quote(blocks));
+        self
+    }
+
+    /// Renders the document in the markup of a service
+    pub fn render(&self, markup: Markup) -> String {
+        render_blocks(&self.blocks, markup)
+    }
+
+    /// Converts the document into one that doesn't borrow any data
+    pub fn into_owned(self) -> Document<'static> {
+        Document {
+            blocks: self.blocks.into_iter().map(Block::into_owned).collect(),
+        }
+    }
+}
+
+impl Document<'static> {
+    /// Fills the placeholders of all texts of the document in place
+    ///
+    /// Values are not escaped here, because all texts are escaped when the document is
+    /// rendered.
+    pub(crate) fn fill(&mut self, ctx: &Context) -> Result<(), crate::Error> {
+        fill_blocks(&mut self.blocks, ctx)
+    }
+}
+
+impl<'a> From<Vec<Block<'a>>> for Document<'a> {
+    fn from(blocks: Vec<Block<'a>>) -> Self {
+        Self { blocks }
+    }
+}
+
+impl<'a> Block<'a> {
+    /// Converts the block into one that doesn't borrow any data
+    pub fn into_owned(self) -> Block<'static> {
+        match self {
+            Block::Paragraph(inlines) => Block::Paragraph(owned_inlines(inlines)),
+            Block::CodeBlock { language, code } => Block::CodeBlock {
+                language: language.map(owned),
+                code: owned(code),
+            },
+            Block::List(items) => Block::List(items.into_iter().map(owned_inlines).collect()),
+            Block::Quote(blocks) => {
+                Block::Quote(blocks.into_iter().map(Block::into_owned).collect())
+            }
+        }
+    }
+}
+
+impl<'a> Inline<'a> {
+    /// Text without formatting
+    pub fn text(text: impl Into<Cow<'a, str>>) -> Self {
+        Inline::Text(text.into())
+    }
+
+    /// Bold text
+    pub fn bold(text: impl Into<Cow<'a, str>>) -> Self {
+        Inline::Bold(vec![Inline::text(text)])
+    }
+
+    /// Italic text
+    pub fn italic(text: impl Into<Cow<'a, str>>) -> Self {
+        Inline::Italic(vec![Inline::text(text)])
+    }
+
+    /// Inline code
+    pub fn code(code: impl Into<Cow<'a, str>>) -> Self {
+        Inline::Code(code.into())
+    }
+
+    /// A link with a text
+    pub fn link(text: impl Into<Cow<'a, str>>, url: impl Into<Cow<'a, str>>) -> Self {
+        Inline::Link {
+            text: vec![Inline::text(text)],
+            url: url.into(),
+        }
+    }
+
+    /// Converts the inline into one that doesn't borrow any data
+    pub fn into_owned(self) -> Inline<'static> {
+        match self {
+            Inline::Text(text) => Inline::Text(owned(text)),
+            Inline::Bold(inlines) => Inline::Bold(owned_inlines(inlines)),
+            Inline::Italic(inlines) => Inline::Italic(owned_inlines(inlines)),
+            Inline::Code(code) => Inline::Code(owned(code)),
+            Inline::Link { text, url } => Inline::Link {
+                text: owned_inlines(text),
+                url: owned(url),
+            },
+            Inline::Mention(name) => Inline::Mention(owned(name)),
+        }
+    }
+}
+
+fn fill_blocks(blocks: &mut [Block<'static>], ctx: &Context) -> Result<(), crate::Error> {
+    for block in blocks {
+        match block {
+            Block::Paragraph(inlines) => fill_inlines(inlines, ctx)?,
+            Block::CodeBlock { code, .. } => template::render_field(code, ctx, Markup::Plain)?,
+            Block::List(items) => {
+                for item in items {
+                    fill_inlines(item, ctx)?;
+                }
+            }
+            Block::Quote(blocks) => fill_blocks(blocks, ctx)?,
+        }
+    }
+    Ok(())
+}
+
+fn fill_inlines(inlines: &mut [Inline<'static>], ctx: &Context) -> Result<(), crate::Error> {
+    for inline in inlines {
+        match inline {
+            Inline::Text(text) | Inline::Code(text) | Inline::Mention(text) => {
+                template::render_field(text, ctx, Markup::Plain)?
+            }
+            Inline::Bold(inlines) | Inline::Italic(inlines) => fill_inlines(inlines, ctx)?,
+            Inline::Link { text, url } => {
+                fill_inlines(text, ctx)?;
+                template::render_field(url, ctx, Markup::Plain)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn owned_inlines(inlines: Vec<Inline<'_>>) -> Vec<Inline<'static>> {
+    inlines.into_iter().map(Inline::into_owned).collect()
+}
+
+fn render_blocks(blocks: &[Block<'_>], markup: Markup) -> String {
+    blocks
+        .iter()
+        .map(|block| render_block(block, markup))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn render_block(block: &Block<'_>, markup: Markup) -> String {
+    match block {
+        Block::Paragraph(inlines) => render_inlines(inlines, markup),
+        Block::CodeBlock { language, code } => match markup {
+            Markup::Discord | Markup::RocketChat => format!(
+                "```{}\n{}\n```",
+                language.as_deref().unwrap_or_default(),
+                code.trim_end_matches('\n')
+            ),
+            Markup::Plain => String::from(code.trim_end_matches('\n')),
+            Markup::Dbus => markup.escape(code.trim_end_matches('\n')).into_owned(),
+        },
+        Block::List(items) => {
+            let bullet = match markup {
+                Markup::Dbus => "• ",
+                _ => "- ",
+            };
+            items
+                .iter()
+                .map(|item| format!("{}{}", bullet, render_inlines(item, markup)))
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        Block::Quote(blocks) => {
+            // the markup of notifications has no quotes, so the marker is escaped there
+            let prefix = match markup {
+                Markup::Dbus => "&gt; ",
+                _ => "> ",
+            };
+            render_blocks(blocks, markup)
+                .lines()
+                .map(|line| format!("{}{}", prefix, line))
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+    }
+}
+
+fn render_inlines(inlines: &[Inline<'_>], markup: Markup) -> String {
+    inlines
+        .iter()
+        .map(|inline| render_inline(inline, markup))
+        .collect()
+}
+
+fn render_inline(inline: &Inline<'_>, markup: Markup) -> String {
+    match inline {
+        Inline::Text(text) => markup.escape(text).into_owned(),
+        Inline::Bold(inlines) => {
+            let inner = render_inlines(inlines, markup);
+            match markup {
+                Markup::Plain => inner,
+                Markup::Discord => format!("**{}**", inner),
+                Markup::RocketChat => format!("*{}*", inner),
+                Markup::Dbus => format!("<b>{}</b>", inner),
+            }
+        }
+        Inline::Italic(inlines) => {
+            let inner = render_inlines(inlines, markup);
+            match markup {
+                Markup::Plain => inner,
+                Markup::Discord | Markup::RocketChat => format!("_{}_", inner),
+                Markup::Dbus => format!("<i>{}</i>", inner),
+            }
+        }
+        Inline::Code(code) => match markup {
+            Markup::Discord | Markup::RocketChat if code.contains('`') => {
+                format!("`` {} ``", code)
+            }
+            Markup::Discord | Markup::RocketChat => format!("`{}`", code),
+            Markup::Plain | Markup::Dbus => markup.escape(code).into_owned(),
+        },
+        Inline::Link { text, url } => {
+            let inner = render_inlines(text, markup);
+            match markup {
+                Markup::Plain if inner == url.as_ref() => inner,
+                Markup::Plain => format!("{} ({})", inner, url),
+                Markup::Discord | Markup::RocketChat => format!("[{}]({})", inner, url),
+                Markup::Dbus => format!(
+                    "<a href=\"{}\">{}</a>",
+                    markup.escape(url).replace('"', "&quot;"),
+                    inner
+                ),
+            }
+        }
+        Inline::Mention(name) => match markup {
+            Markup::Discord if !name.is_empty() && name.chars().all(|c| c.is_ascii_digit()) => {
+                format!("<@{}>", name)
+            }
+            _ => format!("@{}", markup.escape(name)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Block, Document, Inline};
+    use crate::markup::Markup;
+
+    fn document() -> Document<'static> {
+        Document::new()
+            .paragraph(vec![
+                Inline::bold("Alert"),
+                Inline::text(" for "),
+                Inline::Mention("1234".into()),
+                Inline::text(": "),
+                Inline::link("status", "https://status.example.com/?a=1&b=2"),
+            ])
+            .code_block(Some("sh"), "df -h\n")
+            .quote(vec![Block::Paragraph(vec![Inline::italic("disk > 90%")])])
+    }
+
+    #[test]
+    fn test_render_discord() {
+        assert_eq!(
+            "**Alert** for <@1234>: [status](https://status.example.com/?a=1&b=2)\n\
+             ```sh\ndf -h\n```\n\
+             > _disk \\> 90%_",
+            document().render(Markup::Discord)
+        );
+    }
+
+    #[test]
+    fn test_render_rocketchat() {
+        assert_eq!(
+            "*Alert* for @1234: [status](https://status.example.com/?a=1&b=2)\n\
+             ```sh\ndf -h\n```\n\
+             > _disk \\> 90%_",
+            document().render(Markup::RocketChat)
+        );
+    }
+
+    #[test]
+    fn test_render_dbus() {
+        assert_eq!(
+            "<b>Alert</b> for @1234: \
+             <a href=\"https://status.example.com/?a=1&amp;b=2\">status</a>\n\
+             df -h\n\
+             &gt; <i>disk &gt; 90%</i>",
+            document().render(Markup::Dbus)
+        );
+    }
+
+    #[test]
+    fn test_render_plain() {
+        assert_eq!(
+            "Alert for @1234: status (https://status.example.com/?a=1&b=2)\n\
+             df -h\n\
+             > disk > 90%",
+            document().render(Markup::Plain)
+        );
+    }
+
+    #[test]
+    fn test_code_with_backticks() {
+        let doc = Document::new().paragraph(vec![Inline::code("a`b")]);
+        assert_eq!("`` a`b ``", doc.render(Markup::Discord));
+    }
+
+    #[test]
+    fn test_message_prefers_rich_text() {
+        let mut msg = crate::Message::new("{{ name }} failed");
+        assert_eq!(
+            Some("{{ name }} failed"),
+            msg.text_for(Markup::Discord).as_deref()
+        );
+
+        msg.rich_text = Some(
+            Document::new().paragraph(vec![Inline::bold("{{ name }}"), Inline::text(" failed")]),
+        );
+        let ctx = crate::template::Context::new().with("name", "db_main");
+        let msg = msg.render(&ctx, Markup::Discord).unwrap();
+        assert_eq!(
+            Some(r"**db\_main** failed"),
+            msg.text_for(Markup::Discord).as_deref()
+        );
+        assert_eq!(
+            Some("<b>db_main</b> failed"),
+            msg.text_for(Markup::Dbus).as_deref()
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let json = serde_json::to_value(document()).unwrap();
+        assert_eq!("Alert", json[0]["paragraph"][0]["bold"][0]["text"]);
+        let back: Document = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(json, serde_json::to_value(back).unwrap());
+    }
+}
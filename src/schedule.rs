@@ -0,0 +1,314 @@
+//! Sending messages at a later time.
+//!
+//! A [Scheduler] sends a message with [Announce::announce] at a point in time or after a delay,
+//! e.g. a reminder of a maintenance. Every scheduled message has a [Scheduled] handle, which can
+//! cancel it before it is sent:
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//! use announce_lib::schedule::Scheduler;
+//!
+//! # async fn example() -> Result<(), announce_lib::Error> {
+//! let announce = Arc::new(announce_lib::Announce::new().await?);
+//! let scheduler = Scheduler::new(announce);
+//!
+//! let urls = vec![reqwest::Url::parse("discord://WEBHOOK_ID/WEBHOOK_TOKEN")?];
+//! let at = chrono::Utc::now() + chrono::Duration::hours(1);
+//! let reminder = scheduler.at(urls, &announce_lib::Message::new("maintenance in 1 hour"), at)?;
+//!
+//! // the maintenance was moved
+//! assert!(reminder.cancel());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Scheduled messages are kept in memory and are lost when the process exits; the
+//! [outbox](crate::Announce::enqueue) keeps messages that must not get lost. The time to send
+//! is turned into a delay when the message is scheduled, so changes of the system clock
+//! afterwards don't move it.
+
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::{Announce, DeliveryReceipt, Error, Message};
+
+/// Sends messages at a later time, see [the module](self)
+pub struct Scheduler {
+    announce: Arc<Announce>,
+    /// The states of the messages that were scheduled and not sent or cancelled yet
+    scheduled: Mutex<Vec<Arc<Shared>>>,
+}
+
+/// The handle of a scheduled message
+pub struct Scheduled {
+    send_at: DateTime<Utc>,
+    shared: Arc<Shared>,
+    task: tokio::task::JoinHandle<Option<Vec<Result<DeliveryReceipt, Error>>>>,
+}
+
+/// The state of a scheduled message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The message waits for its time
+    Pending,
+    /// The message is sent or was sent
+    Sent,
+    /// The message was cancelled and is not sent
+    Cancelled,
+}
+
+struct Shared {
+    status: Mutex<Status>,
+    cancelled: tokio::sync::Notify,
+}
+
+impl Scheduler {
+    /// Creates a scheduler that sends messages with `announce`
+    pub fn new(announce: Arc<Announce>) -> Self {
+        Self {
+            announce,
+            scheduled: Mutex::new(vec![]),
+        }
+    }
+
+    /// Sends a message at the given time; a time in the past sends it right away.
+    ///
+    /// Returns an error if a url has a scheme without service; nothing is scheduled then.
+    pub fn at(
+        &self,
+        urls: Vec<reqwest::Url>,
+        msg: &Message<'_>,
+        send_at: DateTime<Utc>,
+    ) -> Result<Scheduled, Error> {
+        let delay = (send_at - Utc::now()).to_std().unwrap_or(Duration::ZERO);
+        self.schedule(urls, msg, send_at, delay)
+    }
+
+    /// Sends a message after the given delay.
+    ///
+    /// Returns an error if a url has a scheme without service; nothing is scheduled then.
+    pub fn after(
+        &self,
+        urls: Vec<reqwest::Url>,
+        msg: &Message<'_>,
+        delay: Duration,
+    ) -> Result<Scheduled, Error> {
+        let send_at = chrono::Duration::from_std(delay)
+            .ok()
+            .and_then(|delay| Utc::now().checked_add_signed(delay))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        self.schedule(urls, msg, send_at, delay)
+    }
+
+    /// Returns the number of messages that wait for their time
+    pub fn pending(&self) -> usize {
+        let mut scheduled = self.lock();
+        scheduled.retain(|shared| shared.status() == Status::Pending);
+        scheduled.len()
+    }
+
+    /// Cancels every message that waits for its time and returns how many were cancelled
+    pub fn cancel_all(&self) -> usize {
+        let scheduled = std::mem::take(&mut *self.lock());
+        scheduled.iter().filter(|shared| shared.cancel()).count()
+    }
+
+    fn schedule(
+        &self,
+        urls: Vec<reqwest::Url>,
+        msg: &Message<'_>,
+        send_at: DateTime<Utc>,
+        delay: Duration,
+    ) -> Result<Scheduled, Error> {
+        let services = self.announce.services();
+        if urls.iter().any(|url| services.get(url.scheme()).is_none()) {
+            return Err(Error::NoMatchingSchema);
+        }
+
+        let shared = Arc::new(Shared {
+            status: Mutex::new(Status::Pending),
+            cancelled: tokio::sync::Notify::new(),
+        });
+        let task = tokio::spawn({
+            let announce = self.announce.clone();
+            let shared = shared.clone();
+            let msg = msg.clone().into_owned();
+            async move {
+                tokio::select! {
+                    _ = shared.cancelled.notified() => return None,
+                    _ = tokio::time::sleep(delay) => {}
+                }
+                {
+                    let mut status = shared.lock();
+                    if *status != Status::Pending {
+                        return None;
+                    }
+                    *status = Status::Sent;
+                }
+
+                let targets: Vec<String> = urls.iter().map(|url| announce.redact(url)).collect();
+                let results = announce.announce(urls, &msg).await;
+                for (target, result) in targets.iter().zip(&results) {
+                    if let Err(e) = result {
+                        log::warn!("sending a scheduled message to {} failed: {}", target, e);
+                    }
+                }
+                Some(results)
+            }
+        });
+
+        let mut scheduled = self.lock();
+        scheduled.retain(|shared| shared.status() == Status::Pending);
+        scheduled.push(shared.clone());
+        Ok(Scheduled {
+            send_at,
+            shared,
+            task,
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Arc<Shared>>> {
+        self.scheduled
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Scheduled {
+    /// Returns when the message is sent
+    pub fn send_at(&self) -> DateTime<Utc> {
+        self.send_at
+    }
+
+    /// Returns the state of the message
+    pub fn status(&self) -> Status {
+        self.shared.status()
+    }
+
+    /// Cancels the message.
+    ///
+    /// Returns false if it is already sent or cancelled.
+    pub fn cancel(&self) -> bool {
+        self.shared.cancel()
+    }
+
+    /// Waits until the message is sent and returns the results of [Announce::announce], or
+    /// `None` if it was cancelled
+    pub async fn wait(self) -> Option<Vec<Result<DeliveryReceipt, Error>>> {
+        self.task.await.ok().flatten()
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Status> {
+        self.status.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn status(&self) -> Status {
+        *self.lock()
+    }
+
+    fn cancel(&self) -> bool {
+        let mut status = self.lock();
+        if *status != Status::Pending {
+            return false;
+        }
+        *status = Status::Cancelled;
+        // wakes the task, or lets it return as soon as it waits
+        self.cancelled.notify_one();
+        true
+    }
+}
+
+// the tests send to Discord through a mock transport
+#[cfg(all(test, feature = "discord"))]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{Scheduler, Status};
+    use crate::transport::MockTransport;
+    use crate::{Announce, Message};
+
+    fn scheduler(transport: Arc<MockTransport>) -> Scheduler {
+        let announce = Announce::builder().transport(transport).build().unwrap();
+        Scheduler::new(Arc::new(announce))
+    }
+
+    fn urls() -> Vec<reqwest::Url> {
+        vec![reqwest::Url::parse("discord://1/token").unwrap()]
+    }
+
+    #[tokio::test]
+    async fn test_send_later() {
+        let transport = Arc::new(MockTransport::new());
+        let scheduler = scheduler(transport.clone());
+
+        let later = scheduler
+            .after(urls(), &Message::new("later"), Duration::from_millis(30))
+            .unwrap();
+        let past = chrono::Utc::now() - chrono::Duration::seconds(10);
+        let now = scheduler.at(urls(), &Message::new("now"), past).unwrap();
+        assert_eq!(Status::Pending, later.status());
+        assert_eq!(2, scheduler.pending());
+
+        let results = now.wait().await.unwrap();
+        assert!(results[0].is_ok());
+        assert_eq!(1, transport.requests().len());
+        assert_eq!(1, scheduler.pending());
+
+        assert!(later.send_at() > chrono::Utc::now());
+        let results = later.wait().await.unwrap();
+        assert!(results[0].is_ok());
+        let requests = transport.requests();
+        assert_eq!("now", requests[0].json().unwrap()["content"]);
+        assert_eq!("later", requests[1].json().unwrap()["content"]);
+        assert_eq!(0, scheduler.pending());
+
+        assert!(matches!(
+            scheduler.after(
+                vec![reqwest::Url::parse("unknown://x").unwrap()],
+                &Message::new("text"),
+                Duration::ZERO
+            ),
+            Err(crate::Error::NoMatchingSchema)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let transport = Arc::new(MockTransport::new());
+        let scheduler = scheduler(transport.clone());
+
+        let cancelled = scheduler
+            .after(
+                urls(),
+                &Message::new("cancelled"),
+                Duration::from_millis(20),
+            )
+            .unwrap();
+        assert!(cancelled.cancel());
+        assert!(!cancelled.cancel());
+        assert_eq!(Status::Cancelled, cancelled.status());
+        assert!(cancelled.wait().await.is_none());
+
+        let sent = scheduler
+            .after(urls(), &Message::new("sent"), Duration::ZERO)
+            .unwrap();
+        let all = scheduler
+            .after(urls(), &Message::new("all"), Duration::from_secs(3600))
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!sent.cancel());
+        assert_eq!(Status::Sent, sent.status());
+        assert_eq!(1, scheduler.cancel_all());
+        assert_eq!(Status::Cancelled, all.status());
+
+        let requests = transport.requests();
+        assert_eq!(1, requests.len());
+        assert_eq!("sent", requests[0].json().unwrap()["content"]);
+    }
+}
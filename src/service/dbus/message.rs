@@ -1,24 +1,37 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::attachment::Source;
+use crate::message::{owned, Hint, Urgency};
+use crate::template::{self, Context, Markup};
+
 /// Struct that defines a message which can be send via Dbus.
 ///
 /// Notificaiton [Reference](https://specifications.freedesktop.org/notification-spec/notification-spec-latest.html)
+///
+/// When deserializing, missing fields are taken from [Message::default].
+/// The [hints](Message::hints) are not (de)serialized, because they contain D-Bus specific values.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct Message<'a> {
     /// This is the optional name of the application sending the notification.
     /// This should be the application's formal name, rather than some sort of ID.
     /// An example would be "FredApp E-Mail Client," rather than "fredapp-email-client."
-    pub app_name: &'a str,
+    pub app_name: Cow<'a, str>,
 
     /// An optional ID of an existing notification that this notification is intended to replace.
     pub replaces_id: u32,
 
     /// The notification icon.
-    pub app_icon: &'a str,
+    pub app_icon: Cow<'a, str>,
 
     /// This is a single line overview of the notification.
     /// For instance, "You have mail" or "A friend has come online".
     /// It should generally not be longer than 40 characters, though this is not a
     /// requirement, and server implementations should word wrap if necessary.
     /// The summary must be encoded using UTF-8.
-    pub summary: &'a str,
+    pub summary: Cow<'a, str>,
 
     /// This is a multi-line body of text.
     /// Each line is a paragraph, server implementations are free to word wrap
@@ -28,7 +41,7 @@ pub struct Message<'a> {
     /// It must be encoded using UTF-8.
     ///
     /// If the body is omitted, just the summary is displayed
-    pub body: &'a str,
+    pub body: Cow<'a, str>,
 
     /// The actions send a request message back to the notification client when invoked.
     /// This functionality may not be implemented by the notification server,
@@ -46,13 +59,14 @@ pub struct Message<'a> {
     /// The default action (usually invoked my clicking the notification) should
     /// have a key named "default".
     /// The name can be anything, though implementations are free not to display it.
-    pub actions: &'a [&'a str],
+    pub actions: Vec<Cow<'a, str>>,
 
     /// Hints are a way to provide extra data to a notification server that the
     /// server may be able to make use of.
     ///
     /// See Hints for a list of available hints.
-    pub hints: std::collections::HashMap<&'a str, zvariant::Value<'a>>,
+    #[serde(skip)]
+    pub hints: std::collections::HashMap<Cow<'a, str>, zvariant::Value<'a>>,
 
     ///  The timeout time in milliseconds since the display of the notification at
     /// which the notification should automatically close.
@@ -85,43 +99,163 @@ impl<'a> Message<'a> {
             StandardHint::Urgency(byte) => ("urgency", Value::new(byte)),
         };
 
-        self.hints.insert(hint, value);
+        self.hints.insert(Cow::Borrowed(hint), value);
+    }
+
+    /// Converts the message into one that doesn't borrow any data
+    pub fn into_owned(self) -> Message<'static> {
+        Message {
+            app_name: owned(self.app_name),
+            replaces_id: self.replaces_id,
+            app_icon: owned(self.app_icon),
+            summary: owned(self.summary),
+            body: owned(self.body),
+            actions: self.actions.into_iter().map(owned).collect(),
+            hints: self
+                .hints
+                .into_iter()
+                .map(|(key, value)| (owned(key), zvariant::Value::from(value.to_owned())))
+                .collect(),
+            expire_timeout: self.expire_timeout,
+        }
     }
 
-    pub(super) fn from_crate_message(msg: &'a crate::Message) -> Result<Self, crate::Error> {
+    /// Fills the placeholders of this message with the variables of `ctx`
+    ///
+    /// Values in the body are escaped for the markup of notifications, the summary doesn't
+    /// support markup. See [crate::template] for the syntax.
+    pub fn render(&self, ctx: &Context) -> Result<Message<'static>, crate::Error> {
+        let mut msg = self.clone().into_owned();
+        template::render_field(&mut msg.app_name, ctx, Markup::Plain)?;
+        template::render_field(&mut msg.summary, ctx, Markup::Plain)?;
+        template::render_field(&mut msg.body, ctx, Markup::Dbus)?;
+
+        Ok(msg)
+    }
+
+    /// creates a Message from a [crate::Message]
+    pub fn from_crate_message(msg: &'a crate::Message) -> Result<Self, crate::Error> {
         let mut result = Self::default();
-        match msg.text {
+        match msg.text_for(Markup::Dbus) {
             Some(text) => result.body = text,
             None => return Err(crate::Error::Generic(String::from("No Message given"))),
         }
+        let mut fields = vec![];
         for hint in &msg.hints {
             match hint {
-                crate::message::Hint::Link(link) => {
+                Hint::Link(link) => {
                     // body text has priority over link hint
                     if result.body.is_empty() {
-                        result.body = link;
+                        result.body = Cow::Borrowed(link);
+                    }
+                }
+                Hint::Title(title) => result.summary = Cow::Borrowed(title),
+                Hint::Author(author) => result.app_name = Cow::Borrowed(author),
+                Hint::Image(url) => match image_path(url) {
+                    Some(path) => {
+                        result
+                            .hints
+                            .insert(Cow::Borrowed("image-path"), zvariant::Value::from(path));
                     }
+                    None => log::debug!("remote image {} is ignored for service dbus", url),
+                },
+                Hint::Urgency(urgency) => {
+                    result
+                        .hints
+                        .insert(Cow::Borrowed("urgency"), urgency_byte(*urgency));
                 }
-                crate::message::Hint::Description(_) => {}
+                // an urgency is preferred over the urgency of the severity
+                Hint::Severity(severity) => {
+                    result
+                        .hints
+                        .entry(Cow::Borrowed("urgency"))
+                        .or_insert_with(|| urgency_byte(severity.urgency()));
+                }
+                Hint::Field { name, value, .. } => fields.push(format!(
+                    "<b>{}</b>: {}",
+                    Markup::Dbus.escape(name),
+                    Markup::Dbus.escape(value)
+                )),
+                // notifications have no place for these
+                Hint::Description(_)
+                | Hint::Color(_)
+                | Hint::Timestamp(_)
+                | Hint::Footer(_)
+                | Hint::DedupKey(_)
+                | Hint::Tag(_) => {}
             }
         }
-        if msg.file_path.is_some() {
-            log::trace!("file upload is ignored for service dbus");
+        // fields are shown as lines below the body
+        for field in fields {
+            let body = result.body.to_mut();
+            if !body.is_empty() {
+                body.push('\n');
+            }
+            body.push_str(&field);
+        }
+        // the first image file is shown, if there is no image hint; other files are ignored
+        if !result.hints.contains_key("image-path") {
+            let image = msg.attachments.iter().find_map(|file| match &file.source {
+                Source::Path(path)
+                    if file
+                        .mime_type()
+                        .is_some_and(|mime| mime.starts_with("image/")) =>
+                {
+                    Some(path)
+                }
+                _ => None,
+            });
+            if let Some(path) = image {
+                result
+                    .hints
+                    .insert(Cow::Borrowed("image-path"), file_uri(path).into());
+            }
         }
 
         Ok(result)
     }
 }
 
+/// Returns the value of the `urgency` hint of the notification specification
+fn urgency_byte(urgency: Urgency) -> zvariant::Value<'static> {
+    let byte: u8 = match urgency {
+        Urgency::Low => 0,
+        Urgency::Normal => 1,
+        Urgency::Critical => 2,
+    };
+    zvariant::Value::from(byte)
+}
+
+/// Returns the value of the `image-path` hint for the url of an image hint
+///
+/// The specification only allows `file://` uris and names of icons, so remote urls return
+/// `None`. Local paths are converted with [file_uri].
+fn image_path(url: &str) -> Option<String> {
+    match reqwest::Url::parse(url) {
+        Ok(uri) if uri.scheme() == "file" => Some(String::from(url)),
+        Ok(_) => None,
+        Err(_) => Some(file_uri(url)),
+    }
+}
+
+/// Converts a path to a `file://` uri, as the notification server may not share the working
+/// directory
+fn file_uri(path: &str) -> String {
+    std::fs::canonicalize(path)
+        .ok()
+        .and_then(|path| reqwest::Url::from_file_path(path).ok())
+        .map_or_else(|| String::from(path), String::from)
+}
+
 impl<'a> Default for Message<'a> {
     fn default() -> Self {
         Self {
-            app_name: "Announce",
+            app_name: Cow::Borrowed("Announce"),
             replaces_id: 0,
-            app_icon: "dialog-information",
-            summary: "Announce",
-            body: "",
-            actions: &[],
+            app_icon: Cow::Borrowed("dialog-information"),
+            summary: Cow::Borrowed("Announce"),
+            body: Cow::Borrowed(""),
+            actions: vec![],
             hints: std::collections::HashMap::new(),
             expire_timeout: 0,
         }
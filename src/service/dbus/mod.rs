@@ -6,6 +6,7 @@
 /// Message is to be used with this module
 pub mod message;
 
+use crate::preview::{DbusCall, Preview};
 use crate::service::Service;
 pub use message::Message;
 
@@ -14,6 +15,7 @@ pub use message::Message;
 #[zbus::dbus_proxy(assume_defaults = true, interface = "org.freedesktop.Notifications")]
 /// Freedesktop Notification [Reference](https://specifications.freedesktop.org/notification-spec/notification-spec-latest.html)
 trait Notifications {
+    /// Sends a notification to the notification server
     // this function is not defined by us, but the dbus specification
     #[allow(clippy::too_many_arguments)]
     fn notify(
@@ -27,6 +29,9 @@ trait Notifications {
         hints: std::collections::HashMap<&str, zvariant::Value<'_>>,
         expire_timeout: i32,
     ) -> zbus::Result<u32>;
+
+    /// Closes a notification and removes it from the screen
+    fn close_notification(&self, id: u32) -> zbus::Result<()>;
 }
 
 /// A implementation of messaging to a Dbus notification service
@@ -47,7 +52,7 @@ impl Dbus {
         }
 
         let app_name = match url.username() {
-            name if name.is_empty() => None,
+            "" => None,
             name => Some(String::from(name)),
         };
 
@@ -62,28 +67,85 @@ impl Dbus {
     /// Accepts the following url formattings:
     ///* dbus://{APP_NAME@}{ICON_NAME}{:TIMEOUT}
     /// ```no_run
-    /// use announce::service::dbus;
+    /// use announce_lib::service::dbus;
     ///
     /// let mut msg = dbus::Message::default();
-    /// msg.summary = "Header of message";
-    /// msg.body = "Main body of message";
+    /// msg.summary = "Header of message".into();
+    /// msg.body = "Main body of message".into();
     /// // modify msg to your linking
     ///
     /// dbus::Dbus::announce(&msg);
     /// ```
     pub async fn announce(msg: &Message<'_>) -> Result<u32, crate::Error> {
         let connection = zbus::Connection::session().await?;
-        let proxy = NotificationsProxy::new(&connection).await?;
+        Self::send(&connection, msg).await
+    }
+
+    /// Describes the call that [Dbus::announce] would make, without making it
+    pub fn dry_run(msg: &Message<'_>) -> Preview {
+        let mut args = serde_json::Map::new();
+        args.insert("app_name".into(), msg.app_name.as_ref().into());
+        args.insert("replaces_id".into(), msg.replaces_id.into());
+        args.insert("app_icon".into(), msg.app_icon.as_ref().into());
+        args.insert("summary".into(), msg.summary.as_ref().into());
+        args.insert("body".into(), msg.body.as_ref().into());
+        let actions: Vec<&str> = msg.actions.iter().map(AsRef::as_ref).collect();
+        args.insert("actions".into(), actions.into());
+        let hints: serde_json::Map<_, _> = msg
+            .hints
+            .iter()
+            .map(|(key, value)| (key.to_string(), hint_json(value)))
+            .collect();
+        args.insert("hints".into(), hints.into());
+        args.insert("expire_timeout".into(), msg.expire_timeout.into());
+
+        Preview::Dbus(DbusCall {
+            destination: String::from("org.freedesktop.Notifications"),
+            path: String::from("/org/freedesktop/Notifications"),
+            interface: String::from("org.freedesktop.Notifications"),
+            member: String::from("Notify"),
+            args,
+        })
+    }
+
+    /// Returns the notification for a [crate::Message] with the settings of the url
+    fn prepare<'a>(
+        url: &reqwest::Url,
+        msg: &'a crate::Message,
+    ) -> Result<Message<'a>, crate::Error> {
+        let info = Self::from_url(url)?;
+        let mut message = Message::from_crate_message(msg)?;
 
+        if let Some(app_name) = info.app_name {
+            message.app_name = app_name.into();
+        }
+        if let Some(app_icon) = info.app_icon {
+            message.app_icon = app_icon.into();
+        }
+        message.expire_timeout = info.expire_timeout.unwrap_or(message.expire_timeout);
+
+        Ok(message)
+    }
+
+    /// Sends a message over the given connection and returns the id of the notification
+    async fn send(connection: &zbus::Connection, msg: &Message<'_>) -> Result<u32, crate::Error> {
+        let proxy = NotificationsProxy::new(connection).await?;
+
+        let actions: Vec<&str> = msg.actions.iter().map(AsRef::as_ref).collect();
+        let hints = msg
+            .hints
+            .iter()
+            .map(|(key, value)| (key.as_ref(), value.clone()))
+            .collect();
         let reply = proxy
             .notify(
-                msg.app_name,
+                &msg.app_name,
                 msg.replaces_id,
-                msg.app_icon,
-                msg.summary,
-                msg.body,
-                msg.actions,
-                msg.hints.clone(),
+                &msg.app_icon,
+                &msg.summary,
+                &msg.body,
+                &actions,
+                hints,
                 msg.expire_timeout,
             )
             .await?;
@@ -91,14 +153,51 @@ impl Dbus {
 
         Ok(reply)
     }
+
+    /// Returns the id of the notification of a receipt
+    fn notification_id(receipt: &crate::DeliveryReceipt) -> Result<u32, crate::Error> {
+        let id = crate::handle::message_id(receipt)?;
+        id.parse()
+            .map_err(|_| crate::Error::Generic(format!("invalid id of a notification: {}", id)))
+    }
+}
+
+/// Converts the value of a hint to JSON; values without an equivalent are shown as text
+fn hint_json(value: &zvariant::Value<'_>) -> serde_json::Value {
+    use zvariant::Value;
+
+    match value {
+        Value::U8(v) => (*v).into(),
+        Value::Bool(v) => (*v).into(),
+        Value::I16(v) => (*v).into(),
+        Value::U16(v) => (*v).into(),
+        Value::I32(v) => (*v).into(),
+        Value::U32(v) => (*v).into(),
+        Value::I64(v) => (*v).into(),
+        Value::U64(v) => (*v).into(),
+        Value::F64(v) => (*v).into(),
+        Value::Str(v) => v.as_str().into(),
+        Value::Signature(v) => v.as_str().into(),
+        Value::ObjectPath(v) => v.as_str().into(),
+        Value::Value(v) => hint_json(v),
+        other => format!("{:?}", other).into(),
+    }
 }
 
 #[async_trait::async_trait]
 impl super::Service for Dbus {
+    fn name() -> &'static str {
+        "dbus"
+    }
+
     fn schema() -> Vec<&'static str> {
         vec!["dbus"]
     }
 
+    fn markup() -> crate::markup::Markup {
+        crate::markup::Markup::Dbus
+    }
+
     /// This is used by the announce method in [crate].
     /// Allowed url's are:
     /// * dbus://{APP_NAME@}{ICON_NAME}{:TIMEOUT}
@@ -107,54 +206,174 @@ impl super::Service for Dbus {
         announce: &crate::Announce,
         url: &reqwest::Url,
         msg: &crate::Message,
-    ) -> Result<crate::ReturnType, crate::Error> {
-        let info = Self::from_url(url)?;
-        let proxy = NotificationsProxy::new(&announce.dbus_con).await?;
-        let mut message = Message::from_crate_message(msg)?;
+    ) -> Result<crate::DeliveryReceipt, crate::Error> {
+        let sent_at = chrono::Utc::now();
+        let message = Self::prepare(url, msg)?;
 
-        let app_name = info
-            .app_name
-            .unwrap_or_else(|| String::from(message.app_name));
-        message.app_name = &app_name;
-        let app_icon = info
-            .app_icon
-            .unwrap_or_else(|| String::from(message.app_icon));
-        message.app_icon = &app_icon;
-        message.expire_timeout = info.expire_timeout.unwrap_or(message.expire_timeout);
+        let reply = Self::send(announce.dbus_connection().await?, &message).await?;
 
-        let reply = proxy
-            .notify(
-                message.app_name,
-                message.replaces_id,
-                message.app_icon,
-                message.summary,
-                message.body,
-                message.actions,
-                message.hints,
-                message.expire_timeout,
-            )
-            .await?;
-        log::trace!("{:?}", reply);
+        Ok(crate::DeliveryReceipt::new(
+            Self::name(),
+            Self::redact(url),
+            sent_at,
+            Some(reply.to_string()),
+        ))
+    }
+    /// Sends a notification that replaces the one of the receipt
+    #[doc(hidden)]
+    async fn edit(
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        receipt: &crate::DeliveryReceipt,
+        msg: &crate::Message,
+    ) -> Result<crate::DeliveryReceipt, crate::Error> {
+        let sent_at = chrono::Utc::now();
+        let mut message = Self::prepare(url, msg)?;
+        message.replaces_id = Self::notification_id(receipt)?;
+
+        let reply = Self::send(announce.dbus_connection().await?, &message).await?;
+
+        Ok(crate::DeliveryReceipt::new(
+            Self::name(),
+            Self::redact(url),
+            sent_at,
+            Some(reply.to_string()),
+        ))
+    }
+
+    #[doc(hidden)]
+    async fn delete(
+        announce: &crate::Announce,
+        _url: &reqwest::Url,
+        receipt: &crate::DeliveryReceipt,
+    ) -> Result<(), crate::Error> {
+        let id = Self::notification_id(receipt)?;
+        let proxy = NotificationsProxy::new(announce.dbus_connection().await?).await?;
+        proxy.close_notification(id).await?;
+        Ok(())
+    }
 
-        Ok(crate::ReturnType::Dbus(reply))
+    #[doc(hidden)]
+    async fn preview(
+        _announce: &crate::Announce,
+        url: &reqwest::Url,
+        msg: &crate::Message,
+    ) -> Result<Vec<Preview>, crate::Error> {
+        Ok(vec![Self::dry_run(&Self::prepare(url, msg)?)])
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::field_reassign_with_default, clippy::needless_borrow)]
 mod tests {
     use crate::service::Service;
 
     #[tokio::test]
     async fn test_dbus_announce() {
         let mut msg = super::Message::default();
-        msg.app_name = "Announce";
-        msg.summary = "summary";
-        msg.body = "body";
+        msg.app_name = "Announce".into();
+        msg.summary = "summary".into();
+        msg.body = "body".into();
 
         let result = super::Dbus::announce(&msg).await.unwrap();
         dbg!(result);
     }
 
+    #[test]
+    fn test_message_round_trip() {
+        let json = r#"{ "summary": "summary", "body": "body", "actions": ["default", "Open"] }"#;
+        let msg: super::Message = serde_json::from_str(json).unwrap();
+        assert_eq!("summary", msg.summary);
+        assert_eq!("Announce", msg.app_name);
+        assert_eq!(vec!["default", "Open"], msg.actions);
+
+        let serialized = serde_json::to_value(&msg).unwrap();
+        let back: super::Message = serde_json::from_value(serialized.clone()).unwrap();
+        assert_eq!(serialized, serde_json::to_value(&back).unwrap());
+    }
+
+    #[test]
+    fn test_render() {
+        let template = super::Message {
+            summary: "{{ service }} is down".into(),
+            body: "<b>{{ reason }}</b>".into(),
+            ..Default::default()
+        };
+        let ctx = crate::template::Context::new()
+            .with("service", "<db>")
+            .with("reason", "disk > 90%");
+        let msg = template.render(&ctx).unwrap();
+        assert_eq!("<db> is down", msg.summary);
+        assert_eq!("<b>disk &gt; 90%</b>", msg.body);
+    }
+
+    #[test]
+    fn test_hints() {
+        use crate::message::{Hint, Urgency};
+
+        let mut msg = crate::Message::new("body");
+        msg.hints.push(Hint::Title("title".into()));
+        msg.hints.push(Hint::Urgency(Urgency::Critical));
+        msg.hints.push(Hint::Field {
+            name: "disk".into(),
+            value: "> 90%".into(),
+            inline: false,
+        });
+
+        let message = super::Message::from_crate_message(&msg).unwrap();
+        assert_eq!("title", message.summary);
+        assert_eq!("body\n<b>disk</b>: &gt; 90%", message.body);
+        assert_eq!(Some(&zvariant::Value::U8(2)), message.hints.get("urgency"));
+
+        // the severity is shown as urgency, unless there is an urgency
+        let mut msg = crate::Message::new("body");
+        msg.hints
+            .push(Hint::Severity(crate::message::Severity::Debug));
+        let message = super::Message::from_crate_message(&msg).unwrap();
+        assert_eq!(Some(&zvariant::Value::U8(0)), message.hints.get("urgency"));
+        msg.hints.push(Hint::Urgency(Urgency::Normal));
+        let message = super::Message::from_crate_message(&msg).unwrap();
+        assert_eq!(Some(&zvariant::Value::U8(1)), message.hints.get("urgency"));
+    }
+
+    #[test]
+    fn test_first_image_attachment() {
+        let mut msg = crate::Message::new("body");
+        msg.attachments.push("./sample_uploads/License.md".into());
+        msg.attachments.push("./missing.png".into());
+        msg.attachments
+            .push("./sample_uploads/rustacean-flat-happy.png".into());
+
+        let message = super::Message::from_crate_message(&msg).unwrap();
+        assert_eq!(
+            Some(&zvariant::Value::from("./missing.png")),
+            message.hints.get("image-path")
+        );
+    }
+
+    #[test]
+    fn test_image_hint() {
+        use crate::message::Hint;
+
+        let image = |url: &str| {
+            let mut msg = crate::Message::new("body");
+            msg.hints.push(Hint::Image(url.to_string().into()));
+            let message = super::Message::from_crate_message(&msg).unwrap();
+            let path = message.hints.get("image-path").cloned();
+            path.map(|path| String::try_from(path).unwrap())
+        };
+        assert_eq!(
+            Some("file:///tmp/logo.png"),
+            image("file:///tmp/logo.png").as_deref()
+        );
+        assert_eq!(Some("dialog-warning"), image("dialog-warning").as_deref());
+        // remote images are not allowed by the specification
+        assert_eq!(None, image("https://example.com/logo.png"));
+
+        let local = image("./sample_uploads/rustacean-flat-happy.png").unwrap();
+        assert!(local.starts_with("file:///"));
+    }
+
     #[tokio::test]
     async fn test_dbus_msg() {
         let announce = crate::Announce::new().await.unwrap();
@@ -165,4 +384,23 @@ mod tests {
         let response = super::Dbus::notify(&announce, &url, &msg).await;
         let _ = response.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let announce = crate::Announce::new().await.unwrap();
+        let url = reqwest::Url::parse("dbus://App@localhost:5000").unwrap();
+        let mut msg = crate::Message::new("body");
+        msg.hints
+            .push(crate::Hint::Urgency(crate::message::Urgency::Critical));
+
+        let previews = super::Dbus::preview(&announce, &url, &msg).await.unwrap();
+        let preview = serde_json::to_value(&previews).unwrap();
+        assert_eq!("dbus", preview[0]["kind"]);
+        assert_eq!("Notify", preview[0]["member"]);
+        let args = &preview[0]["args"];
+        assert_eq!("App", args["app_name"]);
+        assert_eq!("body", args["body"]);
+        assert_eq!(5000, args["expire_timeout"]);
+        assert_eq!(2, args["hints"]["urgency"]);
+    }
 }
@@ -1,30 +1,60 @@
 //! Message is to be used with this module
 
-use serde::Serialize;
-
-use crate::message::Message as CrateMessage;
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::attachment::Attachment;
+use crate::limits::{check, length, split_text};
+use crate::message::{owned, Hint, Message as CrateMessage};
+use crate::template::{self, Context, Markup};
+
+/// Maximum number of characters of the content
+pub const MAX_CONTENT: usize = 2000;
+/// Maximum number of embeds of a message
+pub const MAX_EMBEDS: usize = 10;
+/// Maximum number of characters of all embeds of a message together
+pub const MAX_EMBEDS_TOTAL: usize = 6000;
+/// Maximum number of characters of the title of an embed
+pub const MAX_TITLE: usize = 256;
+/// Maximum number of characters of the description of an embed
+pub const MAX_DESCRIPTION: usize = 4096;
+/// Maximum number of fields of an embed
+pub const MAX_FIELDS: usize = 25;
+/// Maximum number of characters of the name of a field
+pub const MAX_FIELD_NAME: usize = 256;
+/// Maximum number of characters of the value of a field
+pub const MAX_FIELD_VALUE: usize = 1024;
+/// Maximum number of characters of the text of a footer
+pub const MAX_FOOTER: usize = 2048;
+/// Maximum number of characters of the name of an author
+pub const MAX_AUTHOR: usize = 256;
+/// Maximum number of files of a message
+pub const MAX_FILES: usize = 10;
 
 /// Represents a message to Discord
 ///
 /// Discord [Reference](https://discord.com/developers/docs/resources/webhook#execute-webhook)
-#[derive(Serialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Message<'a> {
-    /// the message contents (up to 2000 characters)
+    /// the message contents (up to [MAX_CONTENT] characters)
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub content: Option<&'a str>,
+    pub content: Option<Cow<'a, str>>,
 
     /// override the default username of the webhook
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub username: Option<&'a str>,
+    pub username: Option<Cow<'a, str>>,
 
     /// override the default avatar of the webhook
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub avatar_url: Option<&'a str>,
+    pub avatar_url: Option<Cow<'a, str>>,
 
     /// true if this is a TTS message
+    #[serde(default)]
     pub tts: bool,
 
-    /// up to 10 Embeds
+    /// up to [MAX_EMBEDS] Embeds
+    #[serde(default)]
     pub embeds: Vec<Embed<'a>>,
 
     // /// allowed mentions for the message
@@ -34,14 +64,13 @@ pub struct Message<'a> {
     // /// the components to include with the message
     // TODO implement
     // components,
-
-    // /// the contents of the file being sent
-    // TODO implement
-    // files: Vec<File>,
+    /// files that are uploaded with the message (up to [MAX_FILES])
+    #[serde(skip)]
+    pub files: Vec<Attachment<'a>>,
 
     // /// JSON encoded body of non-file params
     // TODO implement
-    // payload_json: Option<&'a str>,
+    // payload_json: Option<Cow<'a, str>>,
 
     // /// attachment objects with filename and description
     // TODO implement
@@ -52,56 +81,251 @@ pub struct Message<'a> {
 
     /// name of thread to create (requires the webhook channel to be a forum channel)
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub thread_name: Option<&'a str>,
+    pub thread_name: Option<Cow<'a, str>>,
 }
 
 impl<'a> Message<'a> {
     /// creates a Message from a [crate::Message]
     pub fn from_crate_message(msg: &'a CrateMessage) -> Message<'a> {
         let mut result = Message {
-            content: msg.text,
+            content: msg.text_for(Markup::Discord),
+            files: msg.attachments.clone(),
             ..Default::default()
         };
+        let mut urgency = None;
         for hint in &msg.hints {
             // assume only up to 1 embed exists
             match hint {
-                crate::message::Hint::Link(link) => {
-                    if let Some(embed) = result.embeds.get_mut(0) {
-                        embed.url = Some(link);
-                    } else {
-                        let embed = Embed::<'_> {
-                            url: Some(link),
-                            ..Default::default()
-                        };
-                        result.embeds.push(embed);
-                    }
+                Hint::Link(link) => first_embed(&mut result).url = Some(Cow::Borrowed(link)),
+                Hint::Description(des) => {
+                    first_embed(&mut result).description = Some(Cow::Borrowed(des))
+                }
+                Hint::Title(title) => first_embed(&mut result).title = Some(Cow::Borrowed(title)),
+                Hint::Color(color) => first_embed(&mut result).color = Some(rgb(*color)),
+                Hint::Image(url) => {
+                    first_embed(&mut result).image = Some(Image {
+                        url: Cow::Borrowed(url),
+                        ..Default::default()
+                    })
+                }
+                Hint::Author(name) => {
+                    first_embed(&mut result).author = Some(Author {
+                        name: Cow::Borrowed(name),
+                        ..Default::default()
+                    })
                 }
-                crate::message::Hint::Description(des) => {
-                    if let Some(embed) = result.embeds.get_mut(0) {
-                        embed.description = Some(des);
-                    } else {
-                        let embed = Embed::<'_> {
-                            description: Some(des),
-                            ..Default::default()
-                        };
-                        result.embeds.push(embed);
-                    }
+                Hint::Timestamp(time) => first_embed(&mut result).timestamp = Some(*time),
+                Hint::Footer(text) => {
+                    first_embed(&mut result).footer = Some(Footer {
+                        text: Some(Cow::Borrowed(text)),
+                        ..Default::default()
+                    })
                 }
+                Hint::Field {
+                    name,
+                    value,
+                    inline,
+                } => first_embed(&mut result).fields.push(Field {
+                    name: Cow::Borrowed(name),
+                    value: Cow::Borrowed(value),
+                    inline: Some(*inline),
+                }),
+                Hint::Urgency(u) => urgency = Some(*u),
+                // an urgency is preferred over the urgency of the severity
+                Hint::Severity(severity) => {
+                    urgency.get_or_insert(severity.urgency());
+                }
+                Hint::DedupKey(_) | Hint::Tag(_) => {}
+            }
+        }
+        // webhooks have no urgency, so it is shown as color
+        if let Some(urgency) = urgency {
+            let embed = first_embed(&mut result);
+            if embed.color.is_none() {
+                embed.color = Some(rgb(urgency.color()));
             }
         }
 
         result
     }
+
+    /// Converts the message into one that doesn't borrow any data
+    pub fn into_owned(self) -> Message<'static> {
+        Message {
+            content: self.content.map(owned),
+            username: self.username.map(owned),
+            avatar_url: self.avatar_url.map(owned),
+            tts: self.tts,
+            embeds: self.embeds.into_iter().map(Embed::into_owned).collect(),
+            files: self.files.into_iter().map(Attachment::into_owned).collect(),
+            flags: self.flags,
+            thread_name: self.thread_name.map(owned),
+        }
+    }
+
+    /// Checks the message against the limits of Discord
+    ///
+    /// Returns [crate::Error::LimitExceeded] for the first limit that is exceeded.
+    /// Discord [Reference](https://discord.com/developers/docs/resources/channel#embed-object-embed-limits)
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        const SERVICE: &str = "discord";
+        check(SERVICE, "content", opt_length(&self.content), MAX_CONTENT)?;
+        check(SERVICE, "embeds", self.embeds.len(), MAX_EMBEDS)?;
+        check(SERVICE, "files", self.files.len(), MAX_FILES)?;
+
+        let mut total = 0;
+        for (i, embed) in self.embeds.iter().enumerate() {
+            let title = opt_length(&embed.title);
+            check(
+                SERVICE,
+                format_args!("embeds[{}].title", i),
+                title,
+                MAX_TITLE,
+            )?;
+            let description = opt_length(&embed.description);
+            check(
+                SERVICE,
+                format_args!("embeds[{}].description", i),
+                description,
+                MAX_DESCRIPTION,
+            )?;
+            let footer = embed
+                .footer
+                .as_ref()
+                .map_or(0, |footer| opt_length(&footer.text));
+            check(
+                SERVICE,
+                format_args!("embeds[{}].footer", i),
+                footer,
+                MAX_FOOTER,
+            )?;
+            let author = embed
+                .author
+                .as_ref()
+                .map_or(0, |author| length(&author.name));
+            check(
+                SERVICE,
+                format_args!("embeds[{}].author", i),
+                author,
+                MAX_AUTHOR,
+            )?;
+            check(
+                SERVICE,
+                format_args!("embeds[{}].fields", i),
+                embed.fields.len(),
+                MAX_FIELDS,
+            )?;
+            total += title + description + footer + author;
+
+            for (j, field) in embed.fields.iter().enumerate() {
+                let name = length(&field.name);
+                check(
+                    SERVICE,
+                    format_args!("embeds[{}].fields[{}].name", i, j),
+                    name,
+                    MAX_FIELD_NAME,
+                )?;
+                let value = length(&field.value);
+                check(
+                    SERVICE,
+                    format_args!("embeds[{}].fields[{}].value", i, j),
+                    value,
+                    MAX_FIELD_VALUE,
+                )?;
+                total += name + value;
+            }
+        }
+        check(SERVICE, "embeds (total)", total, MAX_EMBEDS_TOTAL)
+    }
+
+    /// Splits a message with content that is too long into several messages
+    ///
+    /// The content is split with [crate::limits::split_text]. The embeds and files are sent
+    /// with the last message, the messages before only contain text.
+    pub fn split(self) -> Vec<Message<'a>> {
+        let mut chunks = match &self.content {
+            Some(content) if length(content) > MAX_CONTENT => split_text(content, MAX_CONTENT),
+            _ => return vec![self],
+        };
+
+        let last = chunks.pop().map(Cow::Owned);
+        let mut messages: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| Message {
+                content: Some(Cow::Owned(chunk)),
+                username: self.username.clone(),
+                avatar_url: self.avatar_url.clone(),
+                tts: self.tts,
+                flags: self.flags,
+                ..Default::default()
+            })
+            .collect();
+        messages.push(Message {
+            content: last,
+            ..self
+        });
+
+        messages
+    }
+
+    /// Fills the placeholders of this message with the variables of `ctx`
+    ///
+    /// Values in the content and in the title, description and fields of embeds are escaped for
+    /// Markdown. See [crate::template] for the syntax.
+    pub fn render(&self, ctx: &Context) -> Result<Message<'static>, crate::Error> {
+        let mut msg = self.clone().into_owned();
+        template::render_opt(&mut msg.content, ctx, Markup::Discord)?;
+        template::render_opt(&mut msg.username, ctx, Markup::Plain)?;
+        template::render_opt(&mut msg.thread_name, ctx, Markup::Plain)?;
+        for file in &mut msg.files {
+            file.fill(ctx)?;
+        }
+        for embed in &mut msg.embeds {
+            template::render_opt(&mut embed.title, ctx, Markup::Discord)?;
+            template::render_opt(&mut embed.description, ctx, Markup::Discord)?;
+            template::render_opt(&mut embed.url, ctx, Markup::Plain)?;
+            if let Some(footer) = &mut embed.footer {
+                template::render_opt(&mut footer.text, ctx, Markup::Plain)?;
+            }
+            if let Some(author) = &mut embed.author {
+                template::render_field(&mut author.name, ctx, Markup::Plain)?;
+            }
+            for field in &mut embed.fields {
+                template::render_field(&mut field.name, ctx, Markup::Discord)?;
+                template::render_field(&mut field.value, ctx, Markup::Discord)?;
+            }
+        }
+
+        Ok(msg)
+    }
+}
+
+/// Returns the first embed of a message and creates it if there is none
+fn first_embed<'m, 'a>(msg: &'m mut Message<'a>) -> &'m mut Embed<'a> {
+    if msg.embeds.is_empty() {
+        msg.embeds.push(Embed::default());
+    }
+    &mut msg.embeds[0]
+}
+
+/// Returns the length of an optional text
+fn opt_length(text: &Option<Cow<'_, str>>) -> usize {
+    text.as_deref().map_or(0, length)
+}
+
+/// Converts a color `0xRRGGBB` to the color code of an embed
+fn rgb(color: u32) -> i32 {
+    (color & 0xff_ffff) as i32
 }
 
 /// An Attachment that is embedded in a message
 ///
 /// Embed [Reference](https://discord.com/developers/docs/resources/channel#embed-object)
-#[derive(Serialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Embed<'a> {
     /// title of emebed
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub title: Option<&'a str>,
+    pub title: Option<Cow<'a, str>>,
 
     /// type of [Embed]
     #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
@@ -109,15 +333,16 @@ pub struct Embed<'a> {
 
     /// description of embed
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub description: Option<&'a str>,
+    pub description: Option<Cow<'a, str>>,
 
     /// url of embed
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub url: Option<&'a str>,
+    pub url: Option<Cow<'a, str>>,
+
+    /// timestamp of embed content
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<chrono::DateTime<chrono::Utc>>,
 
-    //TODO add a some sort of ISO8601 compliant type
-    // /// timestamp of embed content
-    // pub timestamp: Option<ISO8601>,
     /// color code of embed
     #[serde(skip_serializing_if = "Option::is_none")]
     pub color: Option<i32>,
@@ -147,15 +372,38 @@ pub struct Embed<'a> {
     pub author: Option<Author<'a>>,
 
     /// fields information
+    #[serde(default)]
     pub fields: Vec<Field<'a>>,
 }
 
+impl<'a> Embed<'a> {
+    /// Converts the embed into one that doesn't borrow any data
+    pub fn into_owned(self) -> Embed<'static> {
+        Embed {
+            title: self.title.map(owned),
+            typ: self.typ,
+            description: self.description.map(owned),
+            url: self.url.map(owned),
+            timestamp: self.timestamp,
+            color: self.color,
+            footer: self.footer.map(Footer::into_owned),
+            image: self.image.map(Image::into_owned),
+            thumbnail: self.thumbnail.map(Thumbnail::into_owned),
+            video: self.video.map(Video::into_owned),
+            provider: self.provider.map(Provider::into_owned),
+            author: self.author.map(Author::into_owned),
+            fields: self.fields.into_iter().map(Field::into_owned).collect(),
+        }
+    }
+}
+
 /// Embedded Type
 ///
 /// Embed types are "loosely defined" and, for the most part, are not used by our clients for rendering. Embed attributes power what is rendered. Embed types should be considered deprecated and might be removed in a future API version.
 ///
 ///Type [Reference](https://discord.com/developers/docs/resources/channel#embed-object-embed-types)
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
 pub enum Typ {
     /// generic embed rendered from embed attributes
     Rich,
@@ -179,33 +427,44 @@ pub enum Typ {
 /// Embedded Footer
 ///
 /// Footer [Reference](https://discord.com/developers/docs/resources/channel#embed-object-embed-footer-structure)
-#[derive(Serialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Footer<'a> {
     /// footer text
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub text: Option<&'a str>,
+    pub text: Option<Cow<'a, str>>,
 
     /// url of footer icon (only supports http(s) and attachments)
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub icon_url: Option<&'a str>,
+    pub icon_url: Option<Cow<'a, str>>,
 
     /// a proxied url of footer icon
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub proxy_icon_url: Option<&'a str>,
+    pub proxy_icon_url: Option<Cow<'a, str>>,
+}
+
+impl<'a> Footer<'a> {
+    /// Converts the footer into one that doesn't borrow any data
+    pub fn into_owned(self) -> Footer<'static> {
+        Footer {
+            text: self.text.map(owned),
+            icon_url: self.icon_url.map(owned),
+            proxy_icon_url: self.proxy_icon_url.map(owned),
+        }
+    }
 }
 
 /// Embedded Thumbnail
 ///
 /// Thumbnail [Reference](https://discord.com/developers/docs/resources/channel#embed-object-embed-thumbnail-structure)
-#[derive(Serialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Thumbnail<'a> {
     /// source url of the thumbnail (only supports http(s) and attachments)
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub url: Option<&'a str>,
+    pub url: Option<Cow<'a, str>>,
 
     /// a proxied url of the thumbnail
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub proxy_url: Option<&'a str>,
+    pub proxy_url: Option<Cow<'a, str>>,
 
     /// height of thumbnail
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -216,18 +475,30 @@ pub struct Thumbnail<'a> {
     pub width: Option<i32>,
 }
 
+impl<'a> Thumbnail<'a> {
+    /// Converts the thumbnail into one that doesn't borrow any data
+    pub fn into_owned(self) -> Thumbnail<'static> {
+        Thumbnail {
+            url: self.url.map(owned),
+            proxy_url: self.proxy_url.map(owned),
+            height: self.height,
+            width: self.width,
+        }
+    }
+}
+
 /// Embedded Video
 ///
 /// Video [Reference](https://discord.com/developers/docs/resources/channel#embed-object-embed-video-structure)
-#[derive(Serialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Video<'a> {
     /// source url of the video
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub url: Option<&'a str>,
+    pub url: Option<Cow<'a, str>>,
 
     /// a proxied url of the video
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub proxy_url: Option<&'a str>,
+    pub proxy_url: Option<Cow<'a, str>>,
 
     /// height of video
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -238,17 +509,29 @@ pub struct Video<'a> {
     pub width: Option<i32>,
 }
 
+impl<'a> Video<'a> {
+    /// Converts the video into one that doesn't borrow any data
+    pub fn into_owned(self) -> Video<'static> {
+        Video {
+            url: self.url.map(owned),
+            proxy_url: self.proxy_url.map(owned),
+            height: self.height,
+            width: self.width,
+        }
+    }
+}
+
 /// Embedded Image
 ///
 /// Image [Reference](https://discord.com/developers/docs/resources/channel#embed-object-embed-image-structure)
-#[derive(Serialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Image<'a> {
     /// source url of image (only supports http(s) and attachments)
-    pub url: &'a str,
+    pub url: Cow<'a, str>,
     #[serde(skip_serializing_if = "Option::is_none")]
 
     /// a proxied url of the image
-    pub proxy: Option<&'a str>,
+    pub proxy: Option<Cow<'a, str>>,
 
     /// height of the image
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -259,53 +542,98 @@ pub struct Image<'a> {
     pub width: Option<i32>,
 }
 
+impl<'a> Image<'a> {
+    /// Converts the image into one that doesn't borrow any data
+    pub fn into_owned(self) -> Image<'static> {
+        Image {
+            url: owned(self.url),
+            proxy: self.proxy.map(owned),
+            height: self.height,
+            width: self.width,
+        }
+    }
+}
+
 /// Embedded Provider
 ///
 /// Provider [Reference](https://discord.com/developers/docs/resources/channel#embed-object-embed-provider-structure)
-#[derive(Serialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Provider<'a> {
     /// name of provider
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub name: Option<&'a str>,
+    pub name: Option<Cow<'a, str>>,
 
     /// url of provider
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub url: Option<&'a str>,
+    pub url: Option<Cow<'a, str>>,
+}
+
+impl<'a> Provider<'a> {
+    /// Converts the provider into one that doesn't borrow any data
+    pub fn into_owned(self) -> Provider<'static> {
+        Provider {
+            name: self.name.map(owned),
+            url: self.url.map(owned),
+        }
+    }
 }
 
 /// Embedded Author
 ///
 /// Author [Reference](https://discord.com/developers/docs/resources/channel#embed-object-embed-author-structure)
-#[derive(Serialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Author<'a> {
     /// name of the author
-    pub name: &'a str,
+    pub name: Cow<'a, str>,
 
     /// url of author
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub url: Option<&'a str>,
+    pub url: Option<Cow<'a, str>>,
 
     /// url of author icon (only supports http(s) and attachments)
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub icon_url: Option<&'a str>,
+    pub icon_url: Option<Cow<'a, str>>,
 
     /// a proxied url of author icon
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub proxy_icon_url: Option<&'a str>,
+    pub proxy_icon_url: Option<Cow<'a, str>>,
+}
+
+impl<'a> Author<'a> {
+    /// Converts the author into one that doesn't borrow any data
+    pub fn into_owned(self) -> Author<'static> {
+        Author {
+            name: owned(self.name),
+            url: self.url.map(owned),
+            icon_url: self.icon_url.map(owned),
+            proxy_icon_url: self.proxy_icon_url.map(owned),
+        }
+    }
 }
 
 /// Embedded Field
 ///
 /// Field [Reference](https://discord.com/developers/docs/resources/channel#embed-object-embed-field-structure)
-#[derive(Serialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Field<'a> {
     /// name of the field
-    pub name: &'a str,
+    pub name: Cow<'a, str>,
 
     /// value of the field
-    pub value: &'a str,
+    pub value: Cow<'a, str>,
 
     /// whether or not this field should display inline
     #[serde(skip_serializing_if = "Option::is_none")]
     pub inline: Option<bool>,
 }
+
+impl<'a> Field<'a> {
+    /// Converts the field into one that doesn't borrow any data
+    pub fn into_owned(self) -> Field<'static> {
+        Field {
+            name: owned(self.name),
+            value: owned(self.value),
+            inline: self.inline,
+        }
+    }
+}
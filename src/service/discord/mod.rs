@@ -4,17 +4,23 @@
 //! * Reference API: <https://discord.com/developers/docs/intro>
 
 pub mod message;
+#[allow(clippy::module_inception, clippy::field_reassign_with_default)]
 mod tests;
 
 use crate::message::Message as CrateMessage;
+use crate::preview::{HttpRequest, Part, Preview};
+use crate::redact::Secret;
+use crate::retry::RetryPolicy;
 use crate::service::Service;
+use crate::transport::Transport;
 
 pub use message::Message;
 
 /// A implementation of messageing to a Discord channel
+#[derive(Debug)]
 pub struct Discord {
     webhook_id: String,
-    webhook_token: String,
+    webhook_token: Secret,
 }
 
 /// A implementation of messaging to Discord
@@ -39,7 +45,7 @@ impl Discord {
 
         Ok(Self {
             webhook_id: String::from(id),
-            webhook_token: token,
+            webhook_token: Secret::new(token),
         })
     }
 
@@ -47,54 +53,338 @@ impl Discord {
     fn build_url(&self) -> Result<reqwest::Url, crate::Error> {
         let url = format!(
             "https://discord.com/api/webhooks/{}/{}",
-            self.webhook_id, self.webhook_token
+            self.webhook_id,
+            self.webhook_token.expose()
         );
         Ok(reqwest::Url::parse(&url)?)
     }
 
+    /// Returns the url of a message that was sent by the webhook
+    ///
+    /// Discord [Reference](https://discord.com/developers/docs/resources/webhook#edit-webhook-message)
+    fn build_message_url(&self, message_id: &str) -> Result<reqwest::Url, crate::Error> {
+        let mut url = self.build_url()?;
+        url.path_segments_mut()
+            .map_err(|_| crate::Error::Generic(String::from("invalid webhook url")))?
+            .extend(["messages", message_id]);
+        Ok(url)
+    }
+
+    /// Builds the request that edits (`PATCH`) or deletes (`DELETE`) a message of the webhook
+    fn build_message_request(
+        client: &reqwest::Client,
+        url: &reqwest::Url,
+        message_id: &str,
+        msg: Option<&Message<'_>>,
+    ) -> Result<reqwest::Request, crate::Error> {
+        let url = Self::from_url(url)?.build_message_url(message_id)?;
+        let builder = match msg {
+            Some(msg) => client
+                .request(reqwest::Method::PATCH, url)
+                .header("Content-Type", "application/json")
+                .json(msg),
+            None => client.request(reqwest::Method::DELETE, url),
+        };
+        Ok(builder.header("Accept", "application/json").build()?)
+    }
+
     /// Accepts the following url formattings;
     /// * discord://WEBHOOK_ID/WEBHOOK_TOKEN
     /// # Example
     /// ```no_run
-    /// use announce::service::discord;
+    /// use announce_lib::service::discord;
     ///
     /// let client = reqwest::Client::new();
     /// let url = "discord://discord_id/discord_token";
     /// let url = reqwest::Url::parse(url).unwrap();
     /// let mut msg = discord::Message::default();
-    /// msg.content = Some("example text");
+    /// msg.content = Some("example text".into());
     /// // modify msg to your linking
     ///
     /// discord::Discord::announce(&client, &url, &msg);
     /// ```
+    ///
+    /// The message is checked with [Message::validate] before it is sent. Failed requests are
+    /// retried according to the default [RetryPolicy]. If Discord rejects the message, the error
+    /// it responded with is returned, see [Discord::check_for_error].
     pub async fn announce(
         client: &reqwest::Client,
         url: &reqwest::Url,
         msg: &Message<'_>,
     ) -> Result<reqwest::Response, crate::Error> {
+        Self::execute(client, client, &RetryPolicy::default(), url, msg).await
+    }
+
+    /// Sends a message like [Discord::announce] with the client, the [Transport] and the
+    /// [RetryPolicy] of `announce`
+    pub async fn announce_with(
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        msg: &Message<'_>,
+    ) -> Result<reqwest::Response, crate::Error> {
+        let policy = announce.retry_policy();
+        Self::execute(&announce.client, announce.transport(), policy, url, msg).await
+    }
+
+    /// Sends a message with `transport` and retries it according to `policy`
+    async fn execute(
+        client: &reqwest::Client,
+        transport: &dyn Transport,
+        policy: &RetryPolicy,
+        url: &reqwest::Url,
+        msg: &Message<'_>,
+    ) -> Result<reqwest::Response, crate::Error> {
+        msg.validate()?;
+        let response = crate::retry::execute(
+            transport,
+            policy,
+            || Discord::build_request(client, url, msg, false),
+            Discord::retry_after,
+        )
+        .await?;
+        Self::error_for_status(response).await
+    }
+
+    /// Describes the request that [Discord::announce] would send, without sending it
+    ///
+    /// The webhook token is replaced with `***`. Files are not read.
+    pub fn dry_run(
+        client: &reqwest::Client,
+        url: &reqwest::Url,
+        msg: &Message<'_>,
+    ) -> Result<Preview, crate::Error> {
+        msg.validate()?;
+        Self::preview_request(client, url, msg, false)
+    }
+
+    /// Returns the messages that are sent for a [crate::Message], which are checked against the
+    /// limits of Discord
+    fn prepare<'a>(
+        announce: &crate::Announce,
+        msg: &'a CrateMessage,
+    ) -> Result<Vec<Message<'a>>, crate::Error> {
+        let msg = Message::from_crate_message(msg);
+        let messages = match announce.split_long_messages() {
+            true => msg.split(),
+            false => vec![msg],
+        };
+        for msg in &messages {
+            msg.validate()?;
+        }
+
+        Ok(messages)
+    }
+
+    /// Splits a batch into the longest parts whose combined message fits into the limits of
+    /// Discord, see [Message::validate]
+    fn batch_chunks<'m, 'a>(msgs: &'m [CrateMessage<'a>]) -> Vec<&'m [CrateMessage<'a>]> {
+        let fits = |chunk: &[CrateMessage<'_>]| {
+            chunk.len() <= message::MAX_FIELDS
+                && Message::from_crate_message(&CrateMessage::combine(chunk))
+                    .validate()
+                    .is_ok()
+        };
+
+        let mut chunks = vec![];
+        let mut rest = msgs;
+        while !rest.is_empty() {
+            // a single message is always sent, so that sending it returns the error
+            let mut len = 1;
+            while len < rest.len() && fits(&rest[..=len]) {
+                len += 1;
+            }
+            let (chunk, tail) = rest.split_at(len);
+            chunks.push(chunk);
+            rest = tail;
+        }
+        chunks
+    }
+
+    /// Returns a builder for a request to a webhook with method, url and headers
+    ///
+    /// With `wait` Discord responds with the created message instead of an empty body.
+    fn request_builder(
+        client: &reqwest::Client,
+        url: &reqwest::Url,
+        wait: bool,
+    ) -> Result<reqwest::RequestBuilder, crate::Error> {
         let info = Self::from_url(url)?;
-        let url = info.build_url()?;
+        let mut url = info.build_url()?;
+        if wait {
+            url.query_pairs_mut().append_pair("wait", "true");
+        }
+
+        Ok(client
+            .request(reqwest::Method::POST, url)
+            .header("Accept", "application/json"))
+    }
+
+    /// Builds the request that sends a message to a webhook
+    ///
+    /// If the message has files, all of them are uploaded with the message in one multipart
+    /// request.
+    async fn build_request(
+        client: &reqwest::Client,
+        url: &reqwest::Url,
+        msg: &Message<'_>,
+        wait: bool,
+    ) -> Result<reqwest::Request, crate::Error> {
+        let builder = Self::request_builder(client, url, wait)?;
+        if msg.files.is_empty() {
+            return Ok(builder
+                .header("Content-Type", "application/json")
+                .json(&msg)
+                .build()?);
+        }
+
+        // Discord [Reference](https://discord.com/developers/docs/reference#uploading-files)
+        let payload = reqwest::multipart::Part::text(serde_json::to_string(msg)?)
+            .mime_str("application/json")?;
+        let mut form = reqwest::multipart::Form::new().part("payload_json", payload);
+        for (i, file) in msg.files.iter().enumerate() {
+            form = form.part(format!("files[{}]", i), file.part().await?);
+        }
+        Ok(builder.multipart(form).build()?)
+    }
+
+    /// Describes the request of [Discord::build_request] without reading the files
+    fn preview_request(
+        client: &reqwest::Client,
+        url: &reqwest::Url,
+        msg: &Message<'_>,
+        wait: bool,
+    ) -> Result<Preview, crate::Error> {
+        let builder = Self::request_builder(client, url, wait)?;
+        if msg.files.is_empty() {
+            let req = builder
+                .header("Content-Type", "application/json")
+                .json(&msg)
+                .build()?;
+            return Ok(Preview::Http(HttpRequest::new(&req)));
+        }
+
+        let mut parts =
+            vec![Part::text("payload_json", serde_json::to_string(msg)?)
+                .with_mime("application/json")];
+        for (i, file) in msg.files.iter().enumerate() {
+            parts.push(Part::file(format!("files[{}]", i), file));
+        }
+        let req = builder.build()?;
+        Ok(Preview::Http(HttpRequest::new(&req).with_parts(parts)))
+    }
+
+    /// Returns the JSON body of a successful response or the error Discord responded with
+    ///
+    /// Discord [Reference](https://discord.com/developers/docs/topics/opcodes-and-status-codes#json)
+    pub async fn check_for_error(
+        response: reqwest::Response,
+    ) -> Result<Option<serde_json::Value>, crate::Error> {
+        let text = Self::error_for_status(response).await?.text().await?;
+        // a response without wait=true has no body
+        Ok(serde_json::from_str(&text).ok())
+    }
+
+    /// Returns a successful response as it is and the error Discord responded with otherwise
+    async fn error_for_status(
+        response: reqwest::Response,
+    ) -> Result<reqwest::Response, crate::Error> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let retry_after = Self::retry_after(response.headers());
+        let text = response.text().await?;
+        let json: Option<serde_json::Value> = serde_json::from_str(&text).ok();
 
-        //build request
-        let builder = client.request(reqwest::Method::POST, url);
-        let req = builder
-            .header("Accept", "application/json")
-            .header("Content-Type", "application/json")
-            .json(&msg)
-            .build()
-            .unwrap();
-        log::trace!("{:?}", req);
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = json
+                .as_ref()
+                .and_then(|json| json.get("retry_after"))
+                .and_then(serde_json::Value::as_f64)
+                .map(std::time::Duration::from_secs_f64)
+                .or(retry_after);
+            return Err(crate::Error::RateLimited { retry_after });
+        }
+        let code = json
+            .as_ref()
+            .and_then(|json| json.get("code"))
+            .map(|code| match code {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            });
+        let message = json
+            .as_ref()
+            .and_then(|json| json.get("message"))
+            .and_then(serde_json::Value::as_str)
+            .map_or(text, String::from);
+        Err(crate::Error::api(
+            Self::name(),
+            Some(status.as_u16()),
+            code,
+            message,
+        ))
+    }
+
+    /// Returns the receipt of a response to `url` or the error Discord responded with
+    pub async fn receipt(
+        url: &reqwest::Url,
+        sent_at: chrono::DateTime<chrono::Utc>,
+        response: reqwest::Response,
+    ) -> Result<crate::DeliveryReceipt, crate::Error> {
+        let status = response.status();
+        let json = Self::check_for_error(response).await?;
+        Ok(crate::DeliveryReceipt::from_json(
+            Self::name(),
+            Self::redact(url),
+            sent_at,
+            status,
+            json.as_ref(),
+            "/id",
+        )
+        .with_channel_id(json.as_ref(), "/channel_id"))
+    }
+
+    /// Sends a message of [Discord::prepare] and returns its receipt
+    async fn send(
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        msg: &Message<'_>,
+    ) -> Result<crate::DeliveryReceipt, crate::Error> {
+        let sent_at = chrono::Utc::now();
+        let response = crate::retry::execute(
+            announce.transport(),
+            &announce.retry,
+            || Discord::build_request(&announce.client, url, msg, true),
+            Discord::retry_after,
+        )
+        .await?;
+        Self::receipt(url, sent_at, response).await
+    }
 
-        Ok(client.execute(req).await?)
+    /// Returns how long Discord wants the client to wait before sending again
+    ///
+    /// Discord [Reference](https://discord.com/developers/docs/topics/rate-limits#header-format)
+    fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<std::time::Duration> {
+        crate::retry::header_f64(headers, "retry-after")
+            .or_else(|| crate::retry::header_f64(headers, "x-ratelimit-reset-after"))
+            .map(std::time::Duration::from_secs_f64)
     }
 }
 
 #[async_trait::async_trait]
 impl super::Service for Discord {
+    fn name() -> &'static str {
+        "discord"
+    }
+
     fn schema() -> Vec<&'static str> {
         vec!["discord"]
     }
 
+    fn markup() -> crate::markup::Markup {
+        crate::markup::Markup::Discord
+    }
+
     /// This is used by the announce method in [crate].
     /// Allowed url's are:
     /// * discord://WEBHOOK_ID/WEBHOOK_TOKEN
@@ -104,10 +394,112 @@ impl super::Service for Discord {
         announce: &crate::Announce,
         url: &reqwest::Url,
         msg: &CrateMessage,
-    ) -> Result<crate::ReturnType, crate::Error> {
+    ) -> Result<crate::DeliveryReceipt, crate::Error> {
+        Self::notify_parts(announce, url, msg, 0).await
+    }
+
+    /// Sends the parts of a split message after the first `sent` ones
+    ///
+    /// Split messages are sent one after another; the receipt of the first one that is sent is
+    /// returned. If a part fails after others were sent,
+    /// [Error::PartiallySent](crate::Error::PartiallySent) tells how many parts were delivered.
+    #[doc(hidden)]
+    async fn notify_parts(
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        msg: &CrateMessage,
+        sent: usize,
+    ) -> Result<crate::DeliveryReceipt, crate::Error> {
+        let messages = Self::prepare(announce, msg)?;
+
+        let mut first = None;
+        for (i, msg) in messages.iter().enumerate().skip(sent) {
+            let receipt = Self::send(announce, url, msg)
+                .await
+                .map_err(|e| super::part_error(i, messages.len(), e))?;
+            first.get_or_insert(receipt);
+        }
+        first.ok_or_else(|| crate::Error::Generic(String::from("nothing to send")))
+    }
+
+    /// Sends the messages as fields of an embed, see [crate::Message::combine]
+    ///
+    /// A batch that exceeds the limits of Discord is sent as several messages; the receipt of
+    /// the first one is returned.
+    #[doc(hidden)]
+    async fn notify_batch(
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        msgs: &[CrateMessage<'_>],
+    ) -> Result<crate::DeliveryReceipt, crate::Error> {
+        let mut first = None;
+        for chunk in Self::batch_chunks(msgs) {
+            let receipt = Self::notify(announce, url, &CrateMessage::combine(chunk)).await?;
+            first.get_or_insert(receipt);
+        }
+        first.ok_or_else(|| crate::Error::Generic(String::from("nothing to send")))
+    }
+
+    /// Edits a message of the webhook; the message is not split.
+    ///
+    /// Returns an error if the message has attachments, the files of a message can't be
+    /// replaced.
+    #[doc(hidden)]
+    async fn edit(
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        receipt: &crate::DeliveryReceipt,
+        msg: &CrateMessage,
+    ) -> Result<crate::DeliveryReceipt, crate::Error> {
+        if !msg.attachments.is_empty() {
+            return Err(crate::Error::Generic(String::from(
+                "files of a message can't be edited",
+            )));
+        }
+        let message_id = crate::handle::message_id(receipt)?;
         let msg = Message::from_crate_message(msg);
-        let response = Discord::announce(&announce.client, url, &msg).await?;
+        msg.validate()?;
+
+        let sent_at = chrono::Utc::now();
+        let response = crate::retry::execute(
+            announce.transport(),
+            &announce.retry,
+            || async {
+                Discord::build_message_request(&announce.client, url, message_id, Some(&msg))
+            },
+            Discord::retry_after,
+        )
+        .await?;
+        Self::receipt(url, sent_at, response).await
+    }
+
+    #[doc(hidden)]
+    async fn delete(
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        receipt: &crate::DeliveryReceipt,
+    ) -> Result<(), crate::Error> {
+        let message_id = crate::handle::message_id(receipt)?;
+        let response = crate::retry::execute(
+            announce.transport(),
+            &announce.retry,
+            || async { Discord::build_message_request(&announce.client, url, message_id, None) },
+            Discord::retry_after,
+        )
+        .await?;
+        Self::check_for_error(response).await?;
+        Ok(())
+    }
 
-        Ok(crate::ReturnType::Reqwest(response))
+    #[doc(hidden)]
+    async fn preview(
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        msg: &CrateMessage,
+    ) -> Result<Vec<Preview>, crate::Error> {
+        Self::prepare(announce, msg)?
+            .iter()
+            .map(|msg| Self::preview_request(&announce.client, url, msg, true))
+            .collect()
     }
 }
@@ -28,12 +28,12 @@ mod tests {
 
         //build message
         let mut msg = Message::default();
-        msg.content = Some("test");
-        msg.username = Some("new_name");
+        msg.content = Some("test".into());
+        msg.username = Some("new_name".into());
         let mut embed = Embed::default();
-        embed.title = Some("embed title");
-        embed.description = Some("embed description");
-        embed.url = Some("https://google.com/");
+        embed.title = Some("embed title".into());
+        embed.description = Some("embed description".into());
+        embed.url = Some("https://google.com/".into());
         // embed.color = Some(40);
         msg.embeds.push(embed);
 
@@ -49,4 +49,400 @@ mod tests {
         dbg!(&status);
         let _ = status.unwrap();
     }
+
+    #[test]
+    fn test_retry_after() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        assert_eq!(None, Discord::retry_after(&headers));
+
+        headers.insert("x-ratelimit-reset-after", "1.5".parse().unwrap());
+        assert_eq!(
+            Some(std::time::Duration::from_millis(1500)),
+            Discord::retry_after(&headers)
+        );
+
+        headers.insert("retry-after", "3".parse().unwrap());
+        assert_eq!(
+            Some(std::time::Duration::from_secs(3)),
+            Discord::retry_after(&headers)
+        );
+    }
+
+    #[test]
+    fn test_redact() {
+        let url = url::Url::parse("discord://1234/secret_token").unwrap();
+        assert_eq!("discord://1234/***", Discord::redact(&url));
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        let json = r#"{
+            "content": "content",
+            "embeds": [{
+                "title": "title",
+                "type": "rich",
+                "fields": [{ "name": "name", "value": "value", "inline": true }],
+                "author": { "name": "author" }
+            }]
+        }"#;
+        let msg: Message = serde_json::from_str(json).unwrap();
+        assert_eq!(Some("content"), msg.content.as_deref());
+        assert!(!msg.tts);
+        assert_eq!("value", msg.embeds[0].fields[0].value);
+        assert_eq!("author", msg.embeds[0].author.as_ref().unwrap().name);
+
+        let serialized = serde_json::to_value(&msg).unwrap();
+        assert_eq!("rich", serialized["embeds"][0]["type"]);
+        let back: Message = serde_json::from_value(serialized.clone()).unwrap();
+        assert_eq!(serialized, serde_json::to_value(&back).unwrap());
+    }
+
+    #[test]
+    fn test_render() {
+        let template = Message {
+            content: Some("{{ service }} is down".into()),
+            embeds: vec![Embed {
+                title: Some("Outage of {{ service }}".into()),
+                url: Some("https://status.example.com/{{ service }}".into()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let ctx = crate::template::Context::new().with("service", "db_main");
+        let msg = template.render(&ctx).unwrap();
+        assert_eq!(Some(r"db\_main is down"), msg.content.as_deref());
+        assert_eq!(Some(r"Outage of db\_main"), msg.embeds[0].title.as_deref());
+        assert_eq!(
+            Some("https://status.example.com/db_main"),
+            msg.embeds[0].url.as_deref()
+        );
+    }
+
+    #[test]
+    fn test_hints() {
+        use crate::message::{Hint, Urgency};
+
+        let mut msg = CrateMessage::new("text");
+        msg.hints.push(Hint::Title("title".into()));
+        msg.hints.push(Hint::Urgency(Urgency::Critical));
+        msg.hints.push(Hint::Field {
+            name: "name".into(),
+            value: "value".into(),
+            inline: true,
+        });
+        msg.hints.push(Hint::Footer("footer".into()));
+
+        let message = Message::from_crate_message(&msg);
+        assert_eq!(1, message.embeds.len());
+        let embed = &message.embeds[0];
+        assert_eq!(Some("title"), embed.title.as_deref());
+        assert_eq!(Some(0xe74c3c), embed.color);
+        assert_eq!("value", embed.fields[0].value);
+        assert_eq!(Some(true), embed.fields[0].inline);
+        assert_eq!(
+            Some("footer"),
+            embed.footer.as_ref().and_then(|f| f.text.as_deref())
+        );
+
+        // an explicit color has priority over the urgency
+        msg.hints.push(Hint::Color(0x00ff00));
+        let message = Message::from_crate_message(&msg);
+        assert_eq!(Some(0x00ff00), message.embeds[0].color);
+
+        // the severity is shown as urgency, tags are not shown
+        let mut msg = CrateMessage::new("text");
+        msg.hints
+            .push(Hint::Severity(crate::message::Severity::Error));
+        msg.hints.push(Hint::Tag("db".into()));
+        let message = Message::from_crate_message(&msg);
+        assert_eq!(Some(0xe74c3c), message.embeds[0].color);
+    }
+
+    #[tokio::test]
+    async fn test_files_are_sent_as_multipart() {
+        let mut msg = CrateMessage::new("text");
+        msg.attachments.push("./sample_uploads/License.md".into());
+        msg.attachments
+            .push("./sample_uploads/rustacean-flat-happy.png".into());
+        let message = Message::from_crate_message(&msg);
+        assert_eq!(2, message.files.len());
+
+        let client = reqwest::Client::new();
+        let url = url::Url::parse("discord://1234/token").unwrap();
+        let req = Discord::build_request(&client, &url, &message, true)
+            .await
+            .unwrap();
+        let content_type = req.headers()["content-type"].to_str().unwrap();
+        assert!(content_type.starts_with("multipart/form-data"));
+
+        // without files the message is sent as json
+        let req = Discord::build_request(&client, &url, &Message::default(), true)
+            .await
+            .unwrap();
+        assert_eq!("application/json", req.headers()["content-type"]);
+    }
+
+    #[test]
+    fn test_limits() {
+        use super::super::message::{Field, MAX_CONTENT};
+
+        let text = "word ".repeat(1000);
+        let mut msg = CrateMessage::new(text.as_str());
+        msg.hints.push(crate::message::Hint::Title("title".into()));
+        let message = Message::from_crate_message(&msg);
+        assert!(matches!(
+            message.validate(),
+            Err(crate::Error::LimitExceeded { ref field, max: MAX_CONTENT, .. }) if field == "content"
+        ));
+
+        // the embed is sent with the last part
+        let messages = message.split();
+        assert_eq!(3, messages.len());
+        assert!(messages.iter().all(|m| m.validate().is_ok()));
+        assert!(messages[0].embeds.is_empty());
+        assert_eq!(1, messages[2].embeds.len());
+        let joined: Vec<_> = messages
+            .iter()
+            .map(|m| m.content.as_deref().unwrap())
+            .collect();
+        assert_eq!(text.trim_end(), joined.join(" "));
+
+        // embeds can't be split
+        let mut message = Message::default();
+        message.embeds.push(Embed::default());
+        for _ in 0..7 {
+            message.embeds[0].fields.push(Field {
+                name: "name".into(),
+                value: "v".repeat(1000).into(),
+                inline: None,
+            });
+        }
+        assert!(matches!(
+            message.split()[0].validate(),
+            Err(crate::Error::LimitExceeded { ref field, .. }) if field == "embeds (total)"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        use crate::preview::{Body, Preview};
+
+        let client = reqwest::Client::new();
+        let url = url::Url::parse("discord://1234/token").unwrap();
+        let msg = Message {
+            content: Some("text".into()),
+            ..Default::default()
+        };
+        let Preview::Http(req) = Discord::dry_run(&client, &url, &msg).unwrap() else {
+            panic!("discord sends http requests");
+        };
+        assert_eq!("POST", req.method);
+        assert_eq!("https://discord.com/api/webhooks/1234/***", req.url);
+        assert!(matches!(&req.body, Body::Json(json) if json["content"] == "text"));
+
+        // the message is split and the file is sent with the last part
+        let announce = crate::Announce::builder()
+            .split_long_messages(true)
+            .build()
+            .unwrap();
+        let mut msg = CrateMessage::new("word ".repeat(500));
+        msg.attachments.push("./sample_uploads/License.md".into());
+        let previews = Discord::preview(&announce, &url, &msg).await.unwrap();
+        assert_eq!(2, previews.len());
+        let json = serde_json::to_value(&previews).unwrap();
+        assert_eq!("application/json", json[0]["headers"]["content-type"]);
+        assert_eq!("multipart", json[1]["body"]["type"]);
+        assert_eq!("payload_json", json[1]["body"]["content"][0]["name"]);
+        assert_eq!("License.md", json[1]["body"]["content"][1]["filename"]);
+        assert!(json[1]["url"].as_str().unwrap().ends_with("/***?wait=true"));
+    }
+
+    #[tokio::test]
+    async fn test_notify_with_mock_transport() {
+        use crate::transport::{MockResponse, MockTransport};
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(MockResponse::rate_limited(Duration::ZERO));
+        transport.push_response(MockResponse::json(200, serde_json::json!({ "id": "42" })));
+        let announce = crate::Announce::builder()
+            .transport(transport.clone())
+            .build()
+            .unwrap();
+
+        let url = url::Url::parse("discord://1234/token").unwrap();
+        let receipt = Discord::notify(&announce, &url, &CrateMessage::new("text"))
+            .await
+            .unwrap();
+        assert!(receipt.success);
+        assert_eq!(Some("42"), receipt.message_id.as_deref());
+
+        // the rate limited request was sent again
+        let requests = transport.requests();
+        assert_eq!(2, requests.len());
+        assert_eq!(
+            "https://discord.com/api/webhooks/1234/token?wait=true",
+            requests[1].url.as_str()
+        );
+        assert_eq!("text", requests[1].json().unwrap()["content"]);
+
+        transport.push_response(MockResponse::json(
+            404,
+            serde_json::json!({ "message": "Unknown Webhook", "code": 10015 }),
+        ));
+        assert!(matches!(
+            Discord::notify(&announce, &url, &CrateMessage::new("text")).await,
+            Err(crate::Error::NotFound { service: "discord", message }) if message == "Unknown Webhook"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_announce_returns_api_errors() {
+        use crate::transport::{MockResponse, MockTransport};
+        use std::sync::Arc;
+
+        let transport = Arc::new(MockTransport::new());
+        let announce = crate::Announce::builder()
+            .transport(transport.clone())
+            .build()
+            .unwrap();
+        let url = url::Url::parse("discord://1234/token").unwrap();
+        let mut msg = Message::default();
+        msg.content = Some("text".into());
+
+        transport.push_response(MockResponse::new(204));
+        let response = Discord::announce_with(&announce, &url, &msg).await.unwrap();
+        assert_eq!(204, response.status().as_u16());
+
+        transport.push_response(MockResponse::json(
+            400,
+            serde_json::json!({ "message": "Invalid Form Body", "code": 50035 }),
+        ));
+        assert!(matches!(
+            Discord::announce_with(&announce, &url, &msg).await,
+            Err(crate::Error::Api { status: Some(400), message, .. }) if message == "Invalid Form Body"
+        ));
+        transport.push_response(MockResponse::new(404));
+        assert!(matches!(
+            Discord::announce_with(&announce, &url, &msg).await,
+            Err(crate::Error::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_split_message_reports_sent_parts() {
+        use crate::transport::{MockResponse, MockTransport};
+        use std::sync::Arc;
+
+        let transport = Arc::new(MockTransport::new());
+        let announce = crate::Announce::builder()
+            .transport(transport.clone())
+            .retry_policy(crate::RetryPolicy::none())
+            .split_long_messages(true)
+            .build()
+            .unwrap();
+        let url = url::Url::parse("discord://1234/token").unwrap();
+        let msg = CrateMessage::new("word ".repeat(900));
+
+        transport.push_response(MockResponse::json(200, serde_json::json!({ "id": "1" })));
+        transport.push_response(MockResponse::new(400));
+        match Discord::notify(&announce, &url, &msg).await {
+            Err(crate::Error::PartiallySent {
+                sent: 1,
+                total: 3,
+                source,
+            }) => assert!(matches!(*source, crate::Error::Api { .. })),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // the parts that were not delivered are sent
+        Discord::notify_parts(&announce, &url, &msg, 1)
+            .await
+            .unwrap();
+        let requests = transport.requests();
+        assert_eq!(4, requests.len());
+        assert_eq!(requests[1].json().unwrap(), requests[2].json().unwrap());
+        assert_ne!(requests[2].json().unwrap(), requests[3].json().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_edit_rejects_files() {
+        use crate::transport::MockTransport;
+        use std::sync::Arc;
+
+        let transport = Arc::new(MockTransport::new());
+        let announce = crate::Announce::builder()
+            .transport(transport.clone())
+            .build()
+            .unwrap();
+        let url = url::Url::parse("discord://1234/token").unwrap();
+        let receipt = crate::DeliveryReceipt::new(
+            "discord",
+            String::from("discord://1234/***"),
+            chrono::Utc::now(),
+            Some(String::from("42")),
+        );
+
+        let mut msg = CrateMessage::new("text");
+        msg.attachments.push("./sample_uploads/License.md".into());
+        assert!(Discord::edit(&announce, &url, &receipt, &msg)
+            .await
+            .is_err());
+        assert!(transport.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_api_errors() {
+        use crate::transport::MockResponse;
+        use std::time::Duration;
+
+        let check = |response: MockResponse| async move {
+            let response = response.into_response().unwrap();
+            Discord::check_for_error(response).await
+        };
+
+        let invalid = serde_json::json!({ "message": "Invalid Form Body", "code": 50035 });
+        match check(MockResponse::json(400, invalid)).await {
+            Err(crate::Error::Api {
+                service,
+                status,
+                code,
+                message,
+            }) => {
+                assert_eq!("discord", service);
+                assert_eq!(Some(400), status);
+                assert_eq!(Some("50035"), code.as_deref());
+                assert_eq!("Invalid Form Body", message);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let token = serde_json::json!({ "message": "Invalid Webhook Token", "code": 50027 });
+        assert!(matches!(
+            check(MockResponse::json(401, token)).await,
+            Err(crate::Error::Unauthorized { .. })
+        ));
+        assert!(matches!(
+            check(MockResponse::new(413).with_body("request entity too large")).await,
+            Err(crate::Error::PayloadTooLarge { message, .. }) if message == "request entity too large"
+        ));
+
+        let limited =
+            serde_json::json!({ "message": "You are being rate limited.", "retry_after": 1.5 });
+        assert!(matches!(
+            check(MockResponse::json(429, limited)).await,
+            Err(crate::Error::RateLimited { retry_after: Some(wait) }) if wait == Duration::from_millis(1500)
+        ));
+
+        assert!(matches!(check(MockResponse::new(204)).await, Ok(None)));
+    }
+
+    #[test]
+    fn test_debug_hides_token() {
+        let url = url::Url::parse("discord://1234/s3cr3t").unwrap();
+        let debug = format!("{:?}", Discord::from_url(&url).unwrap());
+        assert!(debug.contains("webhook_token: ***"));
+        assert!(!debug.contains("s3cr3t"));
+    }
 }
@@ -1,26 +1,27 @@
 //! Contains the services which are supportet by anounce.
 
+use std::marker::PhantomData;
+
+use crate::markup::Markup;
 use crate::message::Message;
+use crate::preview::Preview;
 
 #[cfg(feature = "dbus")]
 pub mod dbus;
 #[cfg(feature = "discord")]
 pub mod discord;
+pub mod registry;
 #[cfg(feature = "rocketchat")]
 pub mod rocketchat;
 
-// /// Type for catching results of services
-// #[derive(Debug)]
-// pub enum ServiceResult {
-//     /// A Request that still needs to be executed
-//     Reqwest(reqwest::Request),
-//     /// A Dbus result
-//     Dbus(u32),
-// }
+pub use registry::ServiceRegistry;
 
 /// A trait implemented for all services
 #[async_trait::async_trait]
 pub trait Service {
+    /// Returns the name of the service
+    fn name() -> &'static str;
+
     /// Returns a Vec of supported schemas
     fn schema() -> Vec<&'static str>;
 
@@ -32,33 +33,410 @@ pub trait Service {
         announce: &crate::Announce,
         target: &reqwest::Url,
         msg: &Message,
-    ) -> Result<crate::ReturnType, crate::Error>;
+    ) -> Result<crate::DeliveryReceipt, crate::Error>;
+
+    // shouldn't be used by the user
+    // either use the crate::dry_run(..) method
+    // or the dry_run method of a specific service
+    #[doc(hidden)]
+    async fn preview(
+        announce: &crate::Announce,
+        target: &reqwest::Url,
+        msg: &Message,
+    ) -> Result<Vec<Preview>, crate::Error>;
+
+    /// Sends a message that is sent in several parts, but skips the first `sent` parts that the
+    /// target already received, see [Error::PartiallySent](crate::Error::PartiallySent)
+    ///
+    /// By default the whole message is sent with [Service::notify].
+    #[doc(hidden)]
+    async fn notify_parts(
+        announce: &crate::Announce,
+        target: &reqwest::Url,
+        msg: &Message,
+        sent: usize,
+    ) -> Result<crate::DeliveryReceipt, crate::Error> {
+        let _ = sent;
+        Self::notify(announce, target, msg).await
+    }
+
+    /// Sends several messages to the target as a single one
+    ///
+    /// By default the messages are [combined](Message::combine) and sent with
+    /// [Service::notify].
+    #[doc(hidden)]
+    async fn notify_batch(
+        announce: &crate::Announce,
+        target: &reqwest::Url,
+        msgs: &[Message<'_>],
+    ) -> Result<crate::DeliveryReceipt, crate::Error> {
+        Self::notify(announce, target, &Message::combine(msgs)).await
+    }
+
+    /// Replaces a message that was sent to the target, see [crate::handle]
+    ///
+    /// By default editing is not supported and an error is returned.
+    #[doc(hidden)]
+    async fn edit(
+        announce: &crate::Announce,
+        target: &reqwest::Url,
+        receipt: &crate::DeliveryReceipt,
+        msg: &Message,
+    ) -> Result<crate::DeliveryReceipt, crate::Error> {
+        let _ = (announce, target, receipt, msg);
+        Err(crate::Error::Generic(format!(
+            "service {} doesn't support editing messages",
+            Self::name()
+        )))
+    }
+
+    /// Removes a message that was sent to the target, see [crate::handle]
+    ///
+    /// By default deleting is not supported and an error is returned.
+    #[doc(hidden)]
+    async fn delete(
+        announce: &crate::Announce,
+        target: &reqwest::Url,
+        receipt: &crate::DeliveryReceipt,
+    ) -> Result<(), crate::Error> {
+        let _ = (announce, target, receipt);
+        Err(crate::Error::Generic(format!(
+            "service {} doesn't support deleting messages",
+            Self::name()
+        )))
+    }
+
+    /// Returns the markup the service uses for text
+    fn markup() -> Markup {
+        Markup::Plain
+    }
 
     /// Returns true if a given url matches with a schema of a given service
     fn match_scheme(url: &reqwest::Url) -> bool {
         Self::schema().iter().any(|s| &url.scheme() == s)
     }
+
+    /// Returns the url as a String that is safe to show, e.g. in a [crate::DeliveryReceipt]
+    ///
+    /// By default secrets are replaced by `***` as described in [crate::redact].
+    fn redact(url: &reqwest::Url) -> String {
+        crate::redact::url(url)
+    }
+}
+
+/// An object safe version of [Service]
+///
+/// Implement this trait to add own services to a [ServiceRegistry] at runtime.
+/// The services of this crate are available through [Builtin].
+#[async_trait::async_trait]
+pub trait DynService: Send + Sync {
+    /// Returns the name of the service
+    fn name(&self) -> &'static str;
+
+    /// Returns a Vec of supported schemas
+    fn schema(&self) -> Vec<&'static str>;
+
+    /// Sends a [crate::Message] to the target described by `url`
+    async fn notify(
+        &self,
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        msg: &Message<'_>,
+    ) -> Result<crate::DeliveryReceipt, crate::Error>;
+
+    /// Describes the requests that [DynService::notify] would send, without sending them
+    ///
+    /// By default dry runs are not supported and an error is returned.
+    async fn preview(
+        &self,
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        msg: &Message<'_>,
+    ) -> Result<Vec<Preview>, crate::Error> {
+        let _ = (announce, url, msg);
+        Err(crate::Error::Generic(format!(
+            "service {} doesn't support dry runs",
+            self.name()
+        )))
+    }
+
+    /// Sends a message that is sent in several parts to the target described by `url`, but skips
+    /// the first `sent` parts that it already received, see
+    /// [Error::PartiallySent](crate::Error::PartiallySent)
+    ///
+    /// By default the whole message is sent with [DynService::notify].
+    async fn notify_parts(
+        &self,
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        msg: &Message<'_>,
+        sent: usize,
+    ) -> Result<crate::DeliveryReceipt, crate::Error> {
+        let _ = sent;
+        self.notify(announce, url, msg).await
+    }
+
+    /// Sends several messages to the target described by `url` as a single one
+    ///
+    /// By default the messages are [combined](Message::combine) and sent with
+    /// [DynService::notify].
+    async fn notify_batch(
+        &self,
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        msgs: &[Message<'_>],
+    ) -> Result<crate::DeliveryReceipt, crate::Error> {
+        self.notify(announce, url, &Message::combine(msgs)).await
+    }
+
+    /// Replaces the message of `receipt` at the target described by `url`
+    ///
+    /// By default editing is not supported and an error is returned.
+    async fn edit(
+        &self,
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        receipt: &crate::DeliveryReceipt,
+        msg: &Message<'_>,
+    ) -> Result<crate::DeliveryReceipt, crate::Error> {
+        let _ = (announce, url, receipt, msg);
+        Err(crate::Error::Generic(format!(
+            "service {} doesn't support editing messages",
+            self.name()
+        )))
+    }
+
+    /// Removes the message of `receipt` from the target described by `url`
+    ///
+    /// By default deleting is not supported and an error is returned.
+    async fn delete(
+        &self,
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        receipt: &crate::DeliveryReceipt,
+    ) -> Result<(), crate::Error> {
+        let _ = (announce, url, receipt);
+        Err(crate::Error::Generic(format!(
+            "service {} doesn't support deleting messages",
+            self.name()
+        )))
+    }
+
+    /// Returns the markup the service uses for text
+    ///
+    /// Templates are escaped for this markup by [crate::Announce::announce_template].
+    fn markup(&self) -> Markup {
+        Markup::Plain
+    }
+
+    /// Returns the url as a String that is safe to show, e.g. in a [crate::DeliveryReceipt]
+    ///
+    /// By default secrets are replaced by `***` as described in [crate::redact].
+    fn redact(&self, url: &reqwest::Url) -> String {
+        crate::redact::url(url)
+    }
 }
 
-/// Tests url with all services and returns a request if it does
+/// Makes a [Service] of this crate usable as [DynService]
+pub struct Builtin<S>(PhantomData<fn() -> S>);
+
+impl<S> Builtin<S> {
+    /// Creates the adapter for service `S`
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<S> Default for Builtin<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: Service + Send> DynService for Builtin<S> {
+    fn name(&self) -> &'static str {
+        S::name()
+    }
+
+    fn schema(&self) -> Vec<&'static str> {
+        S::schema()
+    }
+
+    async fn notify(
+        &self,
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        msg: &Message<'_>,
+    ) -> Result<crate::DeliveryReceipt, crate::Error> {
+        S::notify(announce, url, msg).await
+    }
+
+    async fn preview(
+        &self,
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        msg: &Message<'_>,
+    ) -> Result<Vec<Preview>, crate::Error> {
+        S::preview(announce, url, msg).await
+    }
+
+    async fn notify_parts(
+        &self,
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        msg: &Message<'_>,
+        sent: usize,
+    ) -> Result<crate::DeliveryReceipt, crate::Error> {
+        S::notify_parts(announce, url, msg, sent).await
+    }
+
+    async fn notify_batch(
+        &self,
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        msgs: &[Message<'_>],
+    ) -> Result<crate::DeliveryReceipt, crate::Error> {
+        S::notify_batch(announce, url, msgs).await
+    }
+
+    async fn edit(
+        &self,
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        receipt: &crate::DeliveryReceipt,
+        msg: &Message<'_>,
+    ) -> Result<crate::DeliveryReceipt, crate::Error> {
+        S::edit(announce, url, receipt, msg).await
+    }
+
+    async fn delete(
+        &self,
+        announce: &crate::Announce,
+        url: &reqwest::Url,
+        receipt: &crate::DeliveryReceipt,
+    ) -> Result<(), crate::Error> {
+        S::delete(announce, url, receipt).await
+    }
+
+    fn markup(&self) -> Markup {
+        S::markup()
+    }
+
+    fn redact(&self, url: &reqwest::Url) -> String {
+        S::redact(url)
+    }
+}
+
+/// Looks up the service for the scheme of url and sends the message with it
+///
+/// Repeats are suppressed by the [throttle](crate::throttle) of `announce`, if it has one.
 pub async fn decide_service(
     announce: &crate::Announce,
     url: &reqwest::Url,
     msg: &Message<'_>,
-) -> Result<crate::ReturnType, crate::Error> {
-    //cascade of services
-    #[cfg(feature = "rocketchat")]
-    if rocketchat::RocketChat::match_scheme(url) {
-        return rocketchat::RocketChat::notify(announce, url, msg).await;
+) -> Result<crate::DeliveryReceipt, crate::Error> {
+    decide_parts(announce, url, msg, 0).await
+}
+
+/// Sends the message like [decide_service], but skips the first `sent` parts that the target
+/// already received, see [DynService::notify_parts]
+pub(crate) async fn decide_parts(
+    announce: &crate::Announce,
+    url: &reqwest::Url,
+    msg: &Message<'_>,
+    sent: usize,
+) -> Result<crate::DeliveryReceipt, crate::Error> {
+    let service = announce
+        .services()
+        .get(url.scheme())
+        .ok_or(crate::Error::NoMatchingSchema)?;
+
+    if let Some(throttle) = announce.throttle() {
+        for result in send_summaries(announce).await {
+            if let Err(e) = result {
+                log::warn!("sending a summary of repeats failed: {}", e);
+            }
+        }
+        if let Some(repeats) = throttle.suppress(url, msg) {
+            return Err(crate::Error::Suppressed { repeats });
+        }
+
+        let result = service.notify_parts(announce, url, msg, sent).await;
+        if !matches!(&result, Ok(receipt) if receipt.success) {
+            throttle.forget(url, msg);
+        }
+        return result;
+    }
+
+    service.notify_parts(announce, url, msg, sent).await
+}
+
+/// Returns the error of part `index` of a message with `total` parts
+///
+/// If parts before it were sent, the error is [Error::PartiallySent](crate::Error::PartiallySent).
+#[cfg(any(feature = "discord", feature = "rocketchat"))]
+pub(crate) fn part_error(index: usize, total: usize, error: crate::Error) -> crate::Error {
+    match index {
+        0 => error,
+        sent => crate::Error::PartiallySent {
+            sent,
+            total,
+            source: Box::new(error),
+        },
     }
-    #[cfg(feature = "dbus")]
-    if dbus::Dbus::match_scheme(url) {
-        return dbus::Dbus::notify(announce, url, msg).await;
+}
+
+/// Sends the summaries of the throttle of `announce` whose windows closed
+pub(crate) async fn send_summaries(
+    announce: &crate::Announce,
+) -> Vec<Result<crate::DeliveryReceipt, crate::Error>> {
+    let summaries = match announce.throttle() {
+        Some(throttle) => throttle.take_summaries(),
+        None => return vec![],
+    };
+
+    let mut results = vec![];
+    for (url, msg) in &summaries {
+        let result = match announce.services().get(url.scheme()) {
+            Some(service) => service.notify(announce, url, msg).await,
+            None => Err(crate::Error::NoMatchingSchema),
+        };
+        results.push(result);
     }
-    #[cfg(feature = "discord")]
-    if discord::Discord::match_scheme(url) {
-        return discord::Discord::notify(announce, url, msg).await;
+    results
+}
+
+/// Looks up the service for the scheme of url and sends the messages as a single one
+///
+/// A single message is sent as it is.
+pub async fn decide_batch(
+    announce: &crate::Announce,
+    url: &reqwest::Url,
+    msgs: &[Message<'_>],
+) -> Result<crate::DeliveryReceipt, crate::Error> {
+    let service = announce
+        .services()
+        .get(url.scheme())
+        .ok_or(crate::Error::NoMatchingSchema)?;
+
+    match msgs {
+        [] => Err(crate::Error::Generic(String::from("nothing to send"))),
+        [msg] => service.notify(announce, url, msg).await,
+        msgs => service.notify_batch(announce, url, msgs).await,
     }
+}
+
+/// Looks up the service for the scheme of url and describes what it would send
+pub async fn decide_preview(
+    announce: &crate::Announce,
+    url: &reqwest::Url,
+    msg: &Message<'_>,
+) -> Result<Vec<Preview>, crate::Error> {
+    let service = announce
+        .services()
+        .get(url.scheme())
+        .ok_or(crate::Error::NoMatchingSchema)?;
 
-    Err(crate::Error::NoMatchingSchema)
+    service.preview(announce, url, msg).await
 }
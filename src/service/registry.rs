@@ -0,0 +1,137 @@
+//! Maps url schemes to the services that handle them.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::DynService;
+
+/// Holds the services that are used by [crate::Announce] to send messages.
+///
+/// Every scheme is handled by exactly one service. Registering a service for a scheme that is
+/// already taken replaces the previous service, which allows to override the services of this
+/// crate.
+#[derive(Clone)]
+pub struct ServiceRegistry {
+    services: HashMap<String, Arc<dyn DynService>>,
+}
+
+impl ServiceRegistry {
+    /// Creates a registry containing every service of this crate that is enabled by a feature
+    pub fn new() -> Self {
+        #[allow(unused_mut)]
+        let mut registry = Self::empty();
+
+        #[cfg(feature = "rocketchat")]
+        registry.register(super::Builtin::<super::rocketchat::RocketChat>::new());
+        #[cfg(feature = "dbus")]
+        registry.register(super::Builtin::<super::dbus::Dbus>::new());
+        #[cfg(feature = "discord")]
+        registry.register(super::Builtin::<super::discord::Discord>::new());
+
+        registry
+    }
+
+    /// Creates a registry without any service
+    pub fn empty() -> Self {
+        Self {
+            services: HashMap::new(),
+        }
+    }
+
+    /// Registers a service for all of its schemes
+    pub fn register<S: DynService + 'static>(&mut self, service: S) {
+        self.register_arc(Arc::new(service));
+    }
+
+    /// Registers an already shared service for all of its schemes
+    pub fn register_arc(&mut self, service: Arc<dyn DynService>) {
+        for scheme in service.schema() {
+            if let Some(old) = self.services.insert(String::from(scheme), service.clone()) {
+                log::debug!(
+                    "service {} replaces {} for scheme {}",
+                    service.name(),
+                    old.name(),
+                    scheme
+                );
+            }
+        }
+    }
+
+    /// Removes the service of a scheme and returns it
+    pub fn unregister(&mut self, scheme: &str) -> Option<Arc<dyn DynService>> {
+        self.services.remove(scheme)
+    }
+
+    /// Returns the service that handles a scheme
+    pub fn get(&self, scheme: &str) -> Option<&Arc<dyn DynService>> {
+        self.services.get(scheme)
+    }
+
+    /// Returns all schemes which have a service
+    pub fn schemes(&self) -> impl Iterator<Item = &str> {
+        self.services.keys().map(String::as_str)
+    }
+}
+
+impl Default for ServiceRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ServiceRegistry;
+    use crate::service::DynService;
+
+    struct InHouse;
+
+    #[async_trait::async_trait]
+    impl DynService for InHouse {
+        fn name(&self) -> &'static str {
+            "inhouse"
+        }
+
+        fn schema(&self) -> Vec<&'static str> {
+            vec!["inhouse", "discord"]
+        }
+
+        async fn notify(
+            &self,
+            _announce: &crate::Announce,
+            url: &reqwest::Url,
+            _msg: &crate::Message<'_>,
+        ) -> Result<crate::DeliveryReceipt, crate::Error> {
+            Ok(crate::DeliveryReceipt::new(
+                self.name(),
+                self.redact(url),
+                chrono::Utc::now(),
+                None,
+            ))
+        }
+    }
+
+    #[test]
+    fn test_register() {
+        let mut registry = ServiceRegistry::empty();
+        assert!(registry.get("inhouse").is_none());
+
+        registry.register(InHouse);
+        assert_eq!("inhouse", registry.get("inhouse").unwrap().name());
+        assert_eq!("inhouse", registry.get("discord").unwrap().name());
+
+        let removed = registry.unregister("inhouse").unwrap();
+        assert_eq!("inhouse", removed.name());
+        assert!(registry.get("inhouse").is_none());
+    }
+
+    #[cfg(feature = "discord")]
+    #[test]
+    fn test_override_builtin() {
+        let mut registry = ServiceRegistry::new();
+        assert_eq!("discord", registry.get("discord").unwrap().name());
+
+        registry.register(InHouse);
+        assert_eq!("inhouse", registry.get("discord").unwrap().name());
+    }
+}
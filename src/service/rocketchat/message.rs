@@ -1,11 +1,15 @@
-use serde::Serialize;
+use std::borrow::Cow;
 
-use crate::message::Message as CrateMessage;
+use serde::{Deserialize, Serialize};
+
+use crate::limits::{check, length, split_text};
+use crate::message::{owned, Hint, Message as CrateMessage};
+use crate::template::{self, Context, Markup};
 
 /// Allows for "tables" or "columns" to be displayed on messages.
 ///
 /// Rocket.Chat [Reference](https://developer.rocket.chat/reference/api/rest-api/endpoints/core-endpoints/chat-endpoints/postmessage#attachment-field-objects)
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "snake_case")]
 pub struct Fields<'a> {
     /// Whether this field should be a short field
@@ -13,25 +17,36 @@ pub struct Fields<'a> {
     pub short: bool,
 
     /// The title of the field
-    pub title: &'a str,
+    pub title: Cow<'a, str>,
 
     /// The value of this field, displayed underneath the title value
-    pub value: &'a str,
+    pub value: Cow<'a, str>,
+}
+
+impl<'a> Fields<'a> {
+    /// Converts the field into one that doesn't borrow any data
+    pub fn into_owned(self) -> Fields<'static> {
+        Fields {
+            short: self.short,
+            title: owned(self.title),
+            value: owned(self.value),
+        }
+    }
 }
 
 /// An attachment to a [Message]
 ///
 /// Rocket.Chat [Reference](https://developer.rocket.chat/reference/api/rest-api/endpoints/core-endpoints/chat-endpoints/postmessage#attachments-detail)
-#[derive(Serialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "snake_case")]
 pub struct Attachment<'a> {
     /// The color you want the order on the left side to be.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub color: Option<&'a str>,
+    pub color: Option<Cow<'a, str>>,
 
     /// The Text to display for this attachment, differs from the message's text.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub text: Option<&'a str>,
+    pub text: Option<Cow<'a, str>>,
 
     /// Displays the time next to the text portion.
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -39,62 +54,66 @@ pub struct Attachment<'a> {
 
     /// An image that displays to the left of the text.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub thumb_url: Option<&'a str>,
+    pub thumb_url: Option<Cow<'a, str>>,
 
     /// Only applicable if the ts is provided, as it makes the time clickable to this link.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub message_link: Option<&'a str>,
+    pub message_link: Option<Cow<'a, str>>,
 
     /// Causes the image, audio, and video sections to be hiding when collapsed is true.
+    #[serde(default)]
     pub collapsed: bool,
 
     /// Name of the author.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub author_name: Option<&'a str>,
+    pub author_name: Option<Cow<'a, str>>,
 
     /// Providing this makes the author name clickable and points to this link.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub author_link: Option<&'a str>,
+    pub author_link: Option<Cow<'a, str>>,
 
     /// Displays a tiny icon to the left of the Author's name.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub author_icon: Option<&'a str>,
+    pub author_icon: Option<Cow<'a, str>>,
 
     /// Title to display for this attachment, displays under the author.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub title: Option<&'a str>,
+    pub title: Option<Cow<'a, str>>,
 
     /// Providing this makes the title clickable, pointing to this link.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub title_link: Option<&'a str>,
+    pub title_link: Option<Cow<'a, str>>,
 
     /// When this is true, a download icon appears and clicking this saves the link to file.
+    #[serde(default)]
     pub title_link_download: bool,
 
     /// The image to display, will be "big" and easy to see.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub image_url: Option<&'a str>,
+    pub image_url: Option<Cow<'a, str>>,
 
     /// Video file to play, only supports what [html video](https://developer.mozilla.org/en-US/docs/Web/HTML/Element/video) does.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub video_url: Option<&'a str>,
+    pub video_url: Option<Cow<'a, str>>,
 
     /// Audio file to play, only supports what [html audio](https://developer.mozilla.org/en-US/docs/Web/HTML/Element/audio) does.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub audio_url: Option<&'a str>,
+    pub audio_url: Option<Cow<'a, str>>,
 
     /// An array of [Attachment Field Objects](Fields).
+    #[serde(default)]
     pub fields: Vec<Fields<'a>>,
 }
 
 impl<'a> Attachment<'a> {
     /// decides the type of url by the file ending
-    pub fn link(&mut self, url: &'a str) {
-        let image_endings = vec!["png", "tiff", "jpg", "jpeg", "webp", "gif", "bmp"];
-        let video_endings = vec![
+    pub fn link(&mut self, url: impl Into<Cow<'a, str>>) {
+        let url = url.into();
+        let image_endings = ["png", "tiff", "jpg", "jpeg", "webp", "gif", "bmp"];
+        let video_endings = [
             "mp4", "mkv", "webm", "ogv", "avi", "wmv", "mpg", "mpeg", "flv",
         ];
-        let audio_endings = vec![
+        let audio_endings = [
             "mp3", "opus", "oga", "ogg", "wav", "aac", "wma", "flac", "ape", "webm",
         ];
 
@@ -108,33 +127,72 @@ impl<'a> Attachment<'a> {
             self.message_link = Some(url);
         }
     }
+
+    /// Converts the attachment into one that doesn't borrow any data
+    pub fn into_owned(self) -> Attachment<'static> {
+        Attachment {
+            color: self.color.map(owned),
+            text: self.text.map(owned),
+            ts: self.ts,
+            thumb_url: self.thumb_url.map(owned),
+            message_link: self.message_link.map(owned),
+            collapsed: self.collapsed,
+            author_name: self.author_name.map(owned),
+            author_link: self.author_link.map(owned),
+            author_icon: self.author_icon.map(owned),
+            title: self.title.map(owned),
+            title_link: self.title_link.map(owned),
+            title_link_download: self.title_link_download,
+            image_url: self.image_url.map(owned),
+            video_url: self.video_url.map(owned),
+            audio_url: self.audio_url.map(owned),
+            fields: self.fields.into_iter().map(Fields::into_owned).collect(),
+        }
+    }
+}
+
+/// Returns the first attachment of a message and creates it if there is none
+fn first_attachment<'m, 'a>(msg: &'m mut Message<'a>) -> &'m mut Attachment<'a> {
+    if msg.attachments.is_empty() {
+        msg.attachments.push(Attachment::default());
+    }
+    &mut msg.attachments[0]
+}
+
+/// Converts a color `0xRRGGBB` to the css color of an attachment
+fn rgb(color: u32) -> Cow<'static, str> {
+    Cow::Owned(format!("#{:06x}", color & 0xff_ffff))
 }
 
+/// The default of the setting `Message_MaxAllowedSize` of Rocket.Chat
+pub const DEFAULT_MAX_SIZE: usize = 5000;
+
 /// Main body of a message to be used by this module
 ///
 /// Rocket.Chat [Reference](https://developer.rocket.chat/reference/api/rest-api/endpoints/core-endpoints/chat-endpoints/postmessage#payload)
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Message<'a> {
     /// The channel name of where the message is to be sent.
     channel: String,
 
     /// The text of the message to send, is optional because of attachments.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub text: Option<&'a str>,
+    pub text: Option<Cow<'a, str>>,
 
     /// This will cause the message's name to appear as the given alias, but your username will still display.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub alias: Option<&'a str>,
+    pub alias: Option<Cow<'a, str>>,
 
     /// If provided, this will make the avatar on this message be an [emoji](https://emoji.codes/).
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub emoji: Option<&'a str>,
+    pub emoji: Option<Cow<'a, str>>,
 
     /// If provided, this will make the avatar use the provided image url.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub avatar: Option<&'a str>,
+    pub avatar: Option<Cow<'a, str>>,
 
     /// See [Attachment]
+    #[serde(default)]
     pub attachments: Vec<Attachment<'a>>,
 }
 
@@ -167,55 +225,210 @@ impl<'a> Message<'a> {
         clone.channel = channel;
         clone
     }
-}
 
-/// takes a [crate::Message] and based on the file_path member creates either a [Message]
-/// or a [super::upload::Upload]
-pub(super) fn from_msg<'a>(
-    msg: &'a CrateMessage,
-    channel: &'a str,
-) -> (Option<Message<'a>>, Option<super::upload::Upload<'a>>) {
-    match msg.file_path {
-        Some(path) => {
-            let mut upload = super::upload::Upload::new(path);
-            upload.message = msg.text;
-            for hint in &msg.hints {
-                match hint {
-                    crate::message::Hint::Link(_) => {}
-                    crate::message::Hint::Description(des) => upload.description = Some(des),
+    /// creates a message for a channel from a [crate::Message]
+    ///
+    /// The file of the message is ignored, see [super::RocketChat::upload] for sending files.
+    pub fn from_crate_message(msg: &'a CrateMessage, channel: &str) -> Message<'a> {
+        let mut message = Message::new(channel);
+        message.text = msg.text_for(Markup::RocketChat);
+        let mut urgency = None;
+        for hint in &msg.hints {
+            // assume only up to 1 attachment exists
+            match hint {
+                Hint::Link(url) => first_attachment(&mut message).link(url.as_ref()),
+                Hint::Description(des) => {
+                    first_attachment(&mut message).text = Some(Cow::Borrowed(des))
+                }
+                Hint::Title(title) => {
+                    first_attachment(&mut message).title = Some(Cow::Borrowed(title))
                 }
+                Hint::Color(color) => first_attachment(&mut message).color = Some(rgb(*color)),
+                Hint::Image(url) => {
+                    first_attachment(&mut message).image_url = Some(Cow::Borrowed(url))
+                }
+                Hint::Author(name) => {
+                    first_attachment(&mut message).author_name = Some(Cow::Borrowed(name))
+                }
+                Hint::Timestamp(time) => first_attachment(&mut message).ts = Some(*time),
+                Hint::Footer(_) => log::trace!("footer is ignored for service rocketchat"),
+                Hint::Field {
+                    name,
+                    value,
+                    inline,
+                } => first_attachment(&mut message).fields.push(Fields {
+                    short: *inline,
+                    title: Cow::Borrowed(name),
+                    value: Cow::Borrowed(value),
+                }),
+                Hint::Urgency(u) => urgency = Some(*u),
+                // an urgency is preferred over the urgency of the severity
+                Hint::Severity(severity) => {
+                    urgency.get_or_insert(severity.urgency());
+                }
+                Hint::DedupKey(_) | Hint::Tag(_) => {}
             }
-            (None, Some(super::upload::Upload::new(path)))
         }
-        None => {
-            let mut message = Message::new(channel);
-            message.text = msg.text;
-            for hint in &msg.hints {
-                // assume only up to 1 embed exists
-                match hint {
-                    crate::message::Hint::Link(url) => {
-                        if let Some(attach) = message.attachments.get_mut(0) {
-                            attach.link(url);
-                        } else {
-                            let mut attach = Attachment::default();
-                            attach.link(url);
-                            message.attachments.push(attach);
-                        }
-                    }
-                    crate::message::Hint::Description(des) => {
-                        if let Some(attach) = message.attachments.get_mut(0) {
-                            attach.text = Some(des);
-                        } else {
-                            let attach = Attachment {
-                                text: Some(des),
-                                ..Default::default()
-                            };
-                            message.attachments.push(attach);
-                        }
-                    }
-                }
+        // messages have no urgency, so it is shown as color
+        if let Some(urgency) = urgency {
+            let attach = first_attachment(&mut message);
+            if attach.color.is_none() {
+                attach.color = Some(rgb(urgency.color()));
             }
-            (Some(message), None)
+        }
+        message
+    }
+
+    /// Converts the message into one that doesn't borrow any data
+    pub fn into_owned(self) -> Message<'static> {
+        Message {
+            channel: self.channel,
+            text: self.text.map(owned),
+            alias: self.alias.map(owned),
+            emoji: self.emoji.map(owned),
+            avatar: self.avatar.map(owned),
+            attachments: self
+                .attachments
+                .into_iter()
+                .map(Attachment::into_owned)
+                .collect(),
         }
     }
+
+    /// Checks the text against the maximum size of messages of the Rocket.Chat instance
+    ///
+    /// The size is configured with the setting `Message_MaxAllowedSize`, which is
+    /// [DEFAULT_MAX_SIZE] by default. Returns [crate::Error::LimitExceeded] if the text is
+    /// too long.
+    pub fn validate(&self, max_size: usize) -> Result<(), crate::Error> {
+        let text = self.text.as_deref().map_or(0, length);
+        check("rocketchat", "text", text, max_size)
+    }
+
+    /// Splits a message with a text that is too long into several messages
+    ///
+    /// The text is split with [crate::limits::split_text]. The attachments are sent with the
+    /// last message, the messages before only contain text.
+    pub fn split(self, max_size: usize) -> Vec<Message<'a>> {
+        let mut chunks = match &self.text {
+            Some(text) if length(text) > max_size => split_text(text, max_size),
+            _ => return vec![self],
+        };
+
+        let last = chunks.pop().map(Cow::Owned);
+        let mut messages: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| Message {
+                channel: self.channel.clone(),
+                text: Some(Cow::Owned(chunk)),
+                alias: self.alias.clone(),
+                emoji: self.emoji.clone(),
+                avatar: self.avatar.clone(),
+                attachments: vec![],
+            })
+            .collect();
+        messages.push(Message { text: last, ..self });
+
+        messages
+    }
+
+    /// Fills the placeholders of this message with the variables of `ctx`
+    ///
+    /// Values in the text, the text of attachments and the values of fields are escaped for
+    /// markdown. See [crate::template] for the syntax.
+    pub fn render(&self, ctx: &Context) -> Result<Message<'static>, crate::Error> {
+        let mut msg = self.clone().into_owned();
+        template::render_opt(&mut msg.text, ctx, Markup::RocketChat)?;
+        template::render_opt(&mut msg.alias, ctx, Markup::Plain)?;
+        for attach in &mut msg.attachments {
+            template::render_opt(&mut attach.text, ctx, Markup::RocketChat)?;
+            template::render_opt(&mut attach.title, ctx, Markup::Plain)?;
+            template::render_opt(&mut attach.title_link, ctx, Markup::Plain)?;
+            template::render_opt(&mut attach.message_link, ctx, Markup::Plain)?;
+            template::render_opt(&mut attach.author_name, ctx, Markup::Plain)?;
+            for field in &mut attach.fields {
+                template::render_field(&mut field.title, ctx, Markup::Plain)?;
+                template::render_field(&mut field.value, ctx, Markup::RocketChat)?;
+            }
+        }
+
+        Ok(msg)
+    }
+}
+
+/// Creates one message with an attachment for every message of a batch and an upload for every
+/// file of them
+pub(super) fn from_batch<'a>(
+    msgs: &'a [CrateMessage],
+    channel: &'a str,
+) -> (Message<'a>, Vec<super::upload::Upload<'a>>) {
+    let mut batch = Message::new(channel);
+    batch.text = Some(Cow::Owned(crate::message::count(msgs.len())));
+    let mut uploads = vec![];
+    for msg in msgs {
+        let item = Message::from_crate_message(msg, channel);
+        let mut attachment = item.attachments.into_iter().next().unwrap_or_default();
+        attachment.text = match (item.text, attachment.text) {
+            (Some(text), Some(des)) => Some(Cow::Owned(format!("{}\n{}", text, des))),
+            (text, des) => text.or(des),
+        };
+        batch.attachments.push(attachment);
+        uploads.extend(
+            msg.attachments
+                .iter()
+                .map(|file| super::upload::Upload::new(file.clone())),
+        );
+    }
+    (batch, uploads)
+}
+
+/// takes a [crate::Message] and based on the attachments member creates either a [Message]
+/// or a [super::upload::Upload] for every attachment
+///
+/// The text and the description are sent with the first upload. An upload has no attachments,
+/// so the attachment of the other hints, e.g. a title or fields, is sent as a message without
+/// text alongside the uploads.
+pub(super) fn from_msg<'a>(
+    msg: &'a CrateMessage,
+    channel: &'a str,
+) -> (Option<Message<'a>>, Vec<super::upload::Upload<'a>>) {
+    if msg.attachments.is_empty() {
+        return (Some(Message::from_crate_message(msg, channel)), vec![]);
+    }
+
+    let mut uploads: Vec<_> = msg
+        .attachments
+        .iter()
+        .map(|file| super::upload::Upload::new(file.clone()))
+        .collect();
+    uploads[0].message = msg.text_for(Markup::RocketChat);
+    for hint in &msg.hints {
+        if let Hint::Description(des) = hint {
+            uploads[0].description = Some(Cow::Borrowed(des))
+        }
+    }
+
+    // an urgency alone is only a color, which is not worth a message of its own
+    let has_attachment = msg.hints.iter().any(|hint| {
+        matches!(
+            hint,
+            Hint::Link(_)
+                | Hint::Title(_)
+                | Hint::Color(_)
+                | Hint::Image(_)
+                | Hint::Author(_)
+                | Hint::Timestamp(_)
+                | Hint::Field { .. }
+        )
+    });
+    let body = has_attachment.then(|| {
+        let mut body = Message::from_crate_message(msg, channel);
+        body.text = None;
+        // the description is sent with the upload
+        if let Some(attach) = body.attachments.first_mut() {
+            attach.text = None;
+        }
+        body
+    });
+    (body, uploads)
 }
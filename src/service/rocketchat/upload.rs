@@ -1,49 +1,64 @@
 //! A Module for sending files to a channel in RocketChat
 
+use std::borrow::Cow;
+
+use crate::attachment::Attachment;
+use crate::message::owned;
+use crate::preview::Part;
+
 /// Upload holds all the possible information that can be send with a file
 #[derive(Debug)]
 pub struct Upload<'a> {
     /// A Message to be send with a file
-    pub message: Option<&'a str>,
+    pub message: Option<Cow<'a, str>>,
     /// A description what the file contains
-    pub description: Option<&'a str>,
-    /// The path to the file to send
-    pub file_path: &'a str,
+    pub description: Option<Cow<'a, str>>,
+    /// The file to send
+    pub file: Attachment<'a>,
 }
 
 impl<'a> Upload<'a> {
-    /// creates a new Upload struct
-    pub fn new(file_path: &'a str) -> Upload {
+    /// creates a new Upload struct; a path can be given as file
+    pub fn new(file: impl Into<Attachment<'a>>) -> Upload<'a> {
         Self {
             description: None,
             message: None,
-            file_path,
+            file: file.into(),
+        }
+    }
+
+    /// Converts the upload into one that doesn't borrow any data
+    pub fn into_owned(self) -> Upload<'static> {
+        Upload {
+            message: self.message.map(owned),
+            description: self.description.map(owned),
+            file: self.file.into_owned(),
         }
     }
 
     /// builds the multipart form for streaming a file
     pub(super) async fn build_form(&self) -> Result<reqwest::multipart::Form, crate::Error> {
-        //open file to body stream
-        let file = tokio::fs::File::open(self.file_path).await?;
-        let stream = tokio_util::codec::FramedRead::new(file, tokio_util::codec::BytesCodec::new());
-        let file_body = reqwest::Body::wrap_stream(stream);
-
-        //make form part of file
-        let file_path = String::from(self.file_path);
-        let part = reqwest::multipart::Part::stream(file_body).file_name(file_path);
-        let mime = mime_guess::from_path(self.file_path);
-        let file_part = match mime.first() {
-            None => part,
-            Some(mime) => part.mime_str(mime.essence_str())?,
-        };
+        let file_part = self.file.part().await?;
 
         let mut form = reqwest::multipart::Form::new();
-        if let Some(s) = self.message {
-            form = form.text("msg", String::from(s));
+        if let Some(s) = &self.message {
+            form = form.text("msg", s.to_string());
         };
-        if let Some(s) = self.description {
-            form = form.text("description", String::from(s));
+        if let Some(s) = &self.description {
+            form = form.text("description", s.to_string());
         };
         Ok(form.part("file", file_part))
     }
+    /// describes the parts of the form of [Upload::build_form] without reading the file
+    pub(super) fn preview_parts(&self) -> Vec<Part> {
+        let mut parts = vec![];
+        if let Some(s) = &self.message {
+            parts.push(Part::text("msg", s.as_ref()));
+        }
+        if let Some(s) = &self.description {
+            parts.push(Part::text("description", s.as_ref()));
+        }
+        parts.push(Part::file("file", &self.file));
+        parts
+    }
 }
@@ -0,0 +1,188 @@
+//! Messages with placeholders that are filled from a [Context].
+//!
+//! A placeholder is the name of a variable in double braces, e.g. `{{ service }}`.
+//! Values are escaped for the [Markup] of the field they are inserted into, so a value is always
+//! shown literally.
+//!
+//! Templates are written as normal messages, of this crate or of a service, and are rendered
+//! with their `render` methods:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), announce_lib::Error> {
+//! use announce_lib::template::Context;
+//!
+//! let announce = announce_lib::Announce::new().await?;
+//! let urls = vec![reqwest::Url::parse("discord://WEBHOOK_ID/WEBHOOK_TOKEN")?];
+//! let template = announce_lib::Message::new("Deployment of {{ service }} finished");
+//!
+//! for service in ["api", "worker"] {
+//!     let ctx = Context::new().with("service", service);
+//!     announce.announce_template(urls.clone(), &template, &ctx).await?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+pub use crate::markup::Markup;
+
+/// The variables that are inserted into templates
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    vars: HashMap<String, String>,
+}
+
+impl Context {
+    /// Creates a context without variables
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a variable and returns the context
+    pub fn with(mut self, name: impl Into<String>, value: impl Display) -> Self {
+        self.insert(name, value);
+        self
+    }
+
+    /// Sets a variable; a previous value of the variable is replaced
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Display) {
+        self.vars.insert(name.into(), value.to_string());
+    }
+
+    /// Returns the value of a variable
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(String::as_str)
+    }
+}
+
+impl<K: Into<String>, V: Display> FromIterator<(K, V)> for Context {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut ctx = Self::new();
+        for (name, value) in iter {
+            ctx.insert(name, value);
+        }
+        ctx
+    }
+}
+
+impl From<HashMap<String, String>> for Context {
+    fn from(vars: HashMap<String, String>) -> Self {
+        Self { vars }
+    }
+}
+
+/// Replaces all placeholders of `template` with the escaped values of `ctx`.
+///
+/// Returns [Error::Template](crate::Error::Template) if a variable is missing in the context or
+/// a placeholder is not closed.
+pub fn render_str(template: &str, ctx: &Context, markup: Markup) -> Result<String, crate::Error> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find("}}").ok_or_else(|| {
+            crate::Error::Template(format!("unclosed placeholder in \"{}\"", template))
+        })?;
+        let name = after[..end].trim();
+        let value = ctx
+            .get(name)
+            .ok_or_else(|| crate::Error::Template(format!("unknown variable \"{}\"", name)))?;
+        rendered.push_str(&markup.escape(value));
+        rest = &after[end + 2..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+/// Renders a field of a message in place
+pub(crate) fn render_field(
+    field: &mut Cow<'static, str>,
+    ctx: &Context,
+    markup: Markup,
+) -> Result<(), crate::Error> {
+    if field.contains("{{") {
+        *field = Cow::Owned(render_str(field, ctx, markup)?);
+    }
+    Ok(())
+}
+
+/// Renders an optional field of a message in place
+pub(crate) fn render_opt(
+    field: &mut Option<Cow<'static, str>>,
+    ctx: &Context,
+    markup: Markup,
+) -> Result<(), crate::Error> {
+    match field {
+        Some(field) => render_field(field, ctx, markup),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_str, Context, Markup};
+    use crate::message::{Hint, Message};
+
+    #[test]
+    fn test_render_str() {
+        let ctx = Context::new().with("name", "api").with("count", 3);
+        assert_eq!(
+            "api failed 3 times",
+            render_str("{{name}} failed {{ count }} times", &ctx, Markup::Plain).unwrap()
+        );
+        assert_eq!(
+            "no placeholders",
+            render_str("no placeholders", &ctx, Markup::Plain).unwrap()
+        );
+
+        assert!(matches!(
+            render_str("{{ missing }}", &ctx, Markup::Plain),
+            Err(crate::Error::Template(_))
+        ));
+        assert!(matches!(
+            render_str("{{ name", &ctx, Markup::Plain),
+            Err(crate::Error::Template(_))
+        ));
+    }
+
+    #[test]
+    fn test_values_are_escaped() {
+        let ctx: Context = [("user", "*admin*")].into_iter().collect();
+        assert_eq!(
+            r"**\*admin\***",
+            render_str("**{{user}}**", &ctx, Markup::Discord).unwrap()
+        );
+        let ctx = Context::new().with("user", "<b>");
+        assert_eq!(
+            "<i>&lt;b&gt;</i>",
+            render_str("<i>{{user}}</i>", &ctx, Markup::Dbus).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_render_message() {
+        let mut template = Message::new("{{ service }} is *down*");
+        template.hints.push(Hint::Link(
+            "https://status.example.com/{{ service }}".into(),
+        ));
+        template
+            .hints
+            .push(Hint::Description("since {{ since }}".into()));
+
+        let ctx = Context::new()
+            .with("service", "db_main")
+            .with("since", "12:00");
+        let msg = template.render(&ctx, Markup::Discord).unwrap();
+        assert_eq!(Some(r"db\_main is *down*"), msg.text.as_deref());
+        // links are never escaped
+        assert!(
+            matches!(&msg.hints[0], Hint::Link(l) if l == "https://status.example.com/db_main")
+        );
+        assert!(matches!(&msg.hints[1], Hint::Description(d) if d == "since 12:00"));
+    }
+}
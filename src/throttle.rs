@@ -0,0 +1,296 @@
+//! Suppression of repeated messages.
+//!
+//! A service that flaps can send the same alert many times a minute. With a throttle the first
+//! message to a target opens a window, and the same message to the same target is not sent again
+//! until the window closes. Instead, a single summary with the number of repeats is sent after
+//! the window closed:
+//!
+//! ```no_run
+//! # fn main() -> Result<(), announce_lib::Error> {
+//! let announce = announce_lib::Announce::builder()
+//!     .throttle(std::time::Duration::from_secs(60))
+//!     .build()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Messages are the same if their text, rich text and hints are equal. A
+//! [Hint::DedupKey](crate::Hint::DedupKey) replaces this fingerprint, e.g. to treat alerts with
+//! different timestamps as repeats. Attachments are not part of the fingerprint.
+//!
+//! A message that could not be sent doesn't open a window, so it can be sent again right away.
+//! Repeats are answered with [Error::Suppressed](crate::Error::Suppressed). Summaries are sent
+//! when a message is sent after their window closed, or by
+//! [Announce::send_summaries](crate::Announce::send_summaries), which can be called
+//! periodically to send them on time.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::message::{Hint, Message};
+use crate::richtext::Inline;
+
+/// Remembers which messages were sent to which targets, see [the module](self)
+pub struct Throttle {
+    window: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The open windows by the fingerprint of target and message
+    windows: HashMap<u64, Window>,
+    /// Summaries of windows that closed and were replaced by a new one
+    closed: Vec<(reqwest::Url, Message<'static>)>,
+}
+
+struct Window {
+    opened_at: Instant,
+    url: reqwest::Url,
+    /// The message that opened the window, kept for its summary
+    msg: Message<'static>,
+    repeats: u32,
+}
+
+impl Throttle {
+    /// Creates a throttle that suppresses repeats within `window`
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Returns how long repeats of a message are suppressed
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Records that `msg` is sent to `url`.
+    ///
+    /// Returns the number of repeats within the window if the message should not be sent.
+    pub(crate) fn suppress(&self, url: &reqwest::Url, msg: &Message<'_>) -> Option<u32> {
+        let key = fingerprint(url, msg);
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        match state.windows.get_mut(&key) {
+            Some(window) if now.duration_since(window.opened_at) < self.window => {
+                window.repeats += 1;
+                return Some(window.repeats);
+            }
+            _ => {}
+        }
+
+        let window = Window {
+            opened_at: now,
+            url: url.clone(),
+            msg: msg.clone().into_owned(),
+            repeats: 0,
+        };
+        if let Some(closed) = state.windows.insert(key, window) {
+            if closed.repeats > 0 {
+                let summary = summary(closed.msg, closed.repeats);
+                state.closed.push((closed.url, summary));
+            }
+        }
+        None
+    }
+
+    /// Closes the window that `msg` to `url` opened, because sending it failed.
+    ///
+    /// Repeats counted in the meantime are dropped with it, so that the next attempt is sent.
+    pub(crate) fn forget(&self, url: &reqwest::Url, msg: &Message<'_>) {
+        let key = fingerprint(url, msg);
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if let Some(window) = state.windows.get(&key) {
+            if now.duration_since(window.opened_at) < self.window {
+                state.windows.remove(&key);
+            }
+        }
+    }
+
+    /// Removes the windows that closed and returns the summaries of those with repeats
+    pub(crate) fn take_summaries(&self) -> Vec<(reqwest::Url, Message<'static>)> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let closed: Vec<u64> = state
+            .windows
+            .iter()
+            .filter(|(_, window)| now.duration_since(window.opened_at) >= self.window)
+            .map(|(key, _)| *key)
+            .collect();
+        let mut summaries = std::mem::take(&mut state.closed);
+        for key in closed {
+            if let Some(window) = state.windows.remove(&key) {
+                if window.repeats > 0 {
+                    summaries.push((window.url, summary(window.msg, window.repeats)));
+                }
+            }
+        }
+
+        summaries
+    }
+
+    /// Returns when the next window with repeats closes, if there is one.
+    ///
+    /// Summaries that are already due are returned as the current time.
+    pub fn next_summary(&self) -> Option<Instant> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if !state.closed.is_empty() {
+            return Some(Instant::now());
+        }
+        state
+            .windows
+            .values()
+            .filter(|window| window.repeats > 0)
+            .map(|window| window.opened_at + self.window)
+            .min()
+    }
+}
+
+/// Returns the fingerprint of a message to a target
+fn fingerprint(url: &reqwest::Url, msg: &Message<'_>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    url.as_str().hash(&mut hasher);
+
+    let key = msg.hints.iter().find_map(|hint| match hint {
+        Hint::DedupKey(key) => Some(key),
+        _ => None,
+    });
+    match key {
+        Some(key) => key.hash(&mut hasher),
+        None => {
+            // hints and rich text don't implement Hash, but their JSON is the same if they are
+            let hints: Vec<_> = msg
+                .hints
+                .iter()
+                .filter(|hint| !matches!(hint, Hint::DedupKey(_)))
+                .collect();
+            let content = serde_json::json!([msg.text, msg.rich_text, hints]);
+            content.to_string().hash(&mut hasher);
+        }
+    }
+
+    hasher.finish()
+}
+
+/// Returns the message that tells how often a message was repeated; attachments are not sent
+/// again
+fn summary(mut msg: Message<'static>, repeats: u32) -> Message<'static> {
+    let note = match repeats {
+        1 => String::from("(repeated 1 time)"),
+        n => format!("(repeated {} times)", n),
+    };
+    msg.attachments.clear();
+    msg.text = Some(match msg.text {
+        Some(text) => format!("{}\n{}", text, note).into(),
+        None => note.clone().into(),
+    });
+    msg.rich_text = msg
+        .rich_text
+        .map(|doc| doc.paragraph(vec![Inline::italic(note)]));
+
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Throttle;
+    use crate::{Hint, Message};
+
+    fn url(url: &str) -> reqwest::Url {
+        reqwest::Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn test_repeats_are_suppressed() {
+        let throttle = Throttle::new(Duration::from_secs(60));
+        let target = url("discord://1234/token");
+        let msg = Message::new("disk full");
+
+        assert_eq!(None, throttle.suppress(&target, &msg));
+        assert_eq!(Some(1), throttle.suppress(&target, &msg));
+        assert_eq!(Some(2), throttle.suppress(&target, &msg));
+
+        // other messages and other targets are not repeats
+        assert_eq!(None, throttle.suppress(&target, &Message::new("disk ok")));
+        assert_eq!(None, throttle.suppress(&url("discord://5678/token"), &msg));
+        let mut with_hint = Message::new("disk full");
+        with_hint.hints.push(Hint::Title("db01".into()));
+        assert_eq!(None, throttle.suppress(&target, &with_hint));
+
+        assert!(throttle.take_summaries().is_empty());
+        assert!(throttle.next_summary().is_some());
+    }
+
+    #[test]
+    fn test_dedup_key() {
+        let throttle = Throttle::new(Duration::from_secs(60));
+        let target = url("discord://1234/token");
+        let mut first = Message::new("disk full at 12:00");
+        first.hints.push(Hint::DedupKey("disk".into()));
+        let mut second = Message::new("disk full at 12:01");
+        second.hints.push(Hint::DedupKey("disk".into()));
+
+        assert_eq!(None, throttle.suppress(&target, &first));
+        assert_eq!(Some(1), throttle.suppress(&target, &second));
+    }
+
+    #[test]
+    fn test_forget() {
+        let throttle = Throttle::new(Duration::from_secs(60));
+        let target = url("discord://1234/token");
+        let msg = Message::new("disk full");
+
+        assert_eq!(None, throttle.suppress(&target, &msg));
+        assert_eq!(Some(1), throttle.suppress(&target, &msg));
+        throttle.forget(&target, &msg);
+        assert_eq!(None, throttle.next_summary());
+        assert_eq!(None, throttle.suppress(&target, &msg));
+        assert_eq!(Some(1), throttle.suppress(&target, &msg));
+    }
+
+    #[test]
+    fn test_summary_after_window() {
+        let throttle = Throttle::new(Duration::from_millis(20));
+        let target = url("discord://1234/token");
+        let mut msg = Message::new("disk full");
+        msg.attachments.push("./sample_uploads/License.md".into());
+
+        assert_eq!(None, throttle.suppress(&target, &msg));
+        assert_eq!(Some(1), throttle.suppress(&target, &msg));
+        assert_eq!(Some(2), throttle.suppress(&target, &msg));
+        std::thread::sleep(Duration::from_millis(30));
+
+        let summaries = throttle.take_summaries();
+        assert_eq!(1, summaries.len());
+        assert_eq!(target, summaries[0].0);
+        assert_eq!(
+            Some("disk full\n(repeated 2 times)"),
+            summaries[0].1.text.as_deref()
+        );
+        assert!(summaries[0].1.attachments.is_empty());
+        assert!(throttle.take_summaries().is_empty());
+        assert_eq!(None, throttle.next_summary());
+
+        // a message after the window opens a new one; the summary of the old one is kept
+        assert_eq!(None, throttle.suppress(&target, &msg));
+        assert_eq!(Some(1), throttle.suppress(&target, &msg));
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(None, throttle.suppress(&target, &msg));
+        let summaries = throttle.take_summaries();
+        assert_eq!(
+            Some("disk full\n(repeated 1 time)"),
+            summaries[0].1.text.as_deref()
+        );
+    }
+}
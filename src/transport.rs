@@ -0,0 +1,68 @@
+//! Sending of requests to HTTP services.
+//!
+//! All requests of an [Announce](crate::Announce) object are executed by a [Transport].
+//! By default this is the [reqwest::Client] of the object; another transport can be set with
+//! [AnnounceBuilder::transport](crate::AnnounceBuilder::transport), e.g. to send requests through
+//! a custom stack or to test without network.
+//!
+//! With the feature `testing` the [MockTransport] records requests and answers them with
+//! scripted responses:
+//!
+//! ```
+//! # #[cfg(all(feature = "testing", feature = "discord"))]
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), announce_lib::Error> {
+//! use std::sync::Arc;
+//! use announce_lib::transport::{MockResponse, MockTransport};
+//!
+//! let transport = Arc::new(MockTransport::new());
+//! transport.push_response(MockResponse::json(200, serde_json::json!({ "id": "42" })));
+//! let announce = announce_lib::Announce::builder()
+//!     .transport(transport.clone())
+//!     .build()?;
+//!
+//! let url = reqwest::Url::parse("discord://WEBHOOK_ID/WEBHOOK_TOKEN")?;
+//! let msg = announce_lib::Message::new("Deployment finished");
+//! let receipt = announce.announce(vec![url], &msg).await.remove(0)?;
+//!
+//! assert_eq!(Some("42"), receipt.message_id.as_deref());
+//! let requests = transport.requests();
+//! assert_eq!("Deployment finished", requests[0].json().unwrap()["content"]);
+//! # Ok(())
+//! # }
+//! # #[cfg(not(all(feature = "testing", feature = "discord")))]
+//! # fn main() {}
+//! ```
+
+use std::sync::Arc;
+
+#[cfg(any(test, feature = "testing"))]
+mod mock;
+
+#[cfg(any(test, feature = "testing"))]
+pub use mock::{MockResponse, MockTransport, RecordedRequest};
+
+/// Executes requests to HTTP services
+#[async_trait::async_trait]
+pub trait Transport: Send + Sync {
+    /// Sends a request and returns the response of the service.
+    ///
+    /// Errors of connections that failed or timed out should be returned as
+    /// [Error::Connection](crate::Error::Connection) or as a [reqwest::Error], so they are
+    /// retried according to the [RetryPolicy](crate::RetryPolicy).
+    async fn execute(&self, req: reqwest::Request) -> Result<reqwest::Response, crate::Error>;
+}
+
+#[async_trait::async_trait]
+impl Transport for reqwest::Client {
+    async fn execute(&self, req: reqwest::Request) -> Result<reqwest::Response, crate::Error> {
+        Ok(reqwest::Client::execute(self, req).await?)
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+    async fn execute(&self, req: reqwest::Request) -> Result<reqwest::Response, crate::Error> {
+        T::execute(self, req).await
+    }
+}
@@ -0,0 +1,164 @@
+//! A transport that records requests and answers them with scripted responses.
+
+use std::collections::VecDeque;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use super::Transport;
+
+/// A [Transport] that doesn't use the network.
+///
+/// Every request is recorded. Requests are answered with the queued responses and errors in
+/// the order they were queued, or with `200 OK` and an empty JSON object if the queue is empty.
+#[derive(Default)]
+pub struct MockTransport {
+    requests: Mutex<Vec<RecordedRequest>>,
+    responses: Mutex<VecDeque<Result<MockResponse, crate::Error>>>,
+}
+
+/// A request that was executed by a [MockTransport]
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    /// The method of the request
+    pub method: reqwest::Method,
+
+    /// The url of the request
+    pub url: reqwest::Url,
+
+    /// The headers of the request
+    pub headers: reqwest::header::HeaderMap,
+
+    /// The body of the request; bodies that are streamed, like multipart forms, are not recorded
+    pub body: Option<Bytes>,
+}
+
+/// A response that is returned by a [MockTransport]
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    /// The HTTP status
+    pub status: u16,
+
+    /// The headers as pairs of name and value
+    pub headers: Vec<(String, String)>,
+
+    /// The body
+    pub body: String,
+}
+
+impl MockTransport {
+    /// Creates a transport without recorded requests and queued responses
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a response for a following request
+    pub fn push_response(&self, response: MockResponse) {
+        lock(&self.responses).push_back(Ok(response));
+    }
+
+    /// Queues an error for a following request, e.g. [Error::Connection](crate::Error::Connection)
+    pub fn push_error(&self, error: crate::Error) {
+        lock(&self.responses).push_back(Err(error));
+    }
+
+    /// Returns all requests that were executed so far
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        lock(&self.requests).clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for MockTransport {
+    async fn execute(&self, req: reqwest::Request) -> Result<reqwest::Response, crate::Error> {
+        lock(&self.requests).push(RecordedRequest {
+            method: req.method().clone(),
+            url: req.url().clone(),
+            headers: req.headers().clone(),
+            body: req
+                .body()
+                .and_then(reqwest::Body::as_bytes)
+                .map(Bytes::copy_from_slice),
+        });
+
+        let scripted = lock(&self.responses).pop_front();
+        match scripted {
+            Some(Ok(response)) => response.into_response(),
+            Some(Err(e)) => Err(e),
+            None => MockResponse::json(200, serde_json::json!({})).into_response(),
+        }
+    }
+}
+
+impl RecordedRequest {
+    /// Returns the value of a header, if it is set and valid text
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)?.to_str().ok()
+    }
+
+    /// Returns the body as text
+    pub fn text(&self) -> Option<String> {
+        self.body
+            .as_ref()
+            .map(|body| String::from_utf8_lossy(body).into_owned())
+    }
+
+    /// Returns the body as JSON, if it is valid JSON
+    pub fn json(&self) -> Option<serde_json::Value> {
+        serde_json::from_slice(self.body.as_ref()?).ok()
+    }
+}
+
+impl MockResponse {
+    /// A response with an empty body
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: String::new(),
+        }
+    }
+
+    /// A response with a JSON body
+    pub fn json(status: u16, json: serde_json::Value) -> Self {
+        Self::new(status)
+            .with_header("content-type", "application/json")
+            .with_body(json.to_string())
+    }
+
+    /// A `429 Too Many Requests` response that asks to wait before sending again
+    pub fn rate_limited(retry_after: Duration) -> Self {
+        Self::new(429).with_header("retry-after", retry_after.as_secs_f64().to_string())
+    }
+
+    /// Adds a header
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sets the body
+    pub fn with_body(mut self, body: impl Into<String>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Converts the response into the one a [MockTransport] returns
+    pub fn into_response(self) -> Result<reqwest::Response, crate::Error> {
+        let mut builder = http::Response::builder().status(self.status);
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+        let response = builder
+            .body(self.body)
+            .map_err(|e| crate::Error::Generic(format!("invalid mock response: {}", e)))?;
+
+        Ok(reqwest::Response::from(response))
+    }
+}
+
+/// Locks a mutex, even if another thread panicked while holding it
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}